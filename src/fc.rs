@@ -1,31 +1,256 @@
-use std::{fmt::Display, str::FromStr};
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    io::{BufRead, Write},
+    str::FromStr,
+};
 
-#[derive(Debug, PartialEq)]
-pub struct Fc(pub usize, pub usize, pub usize, pub usize, pub f64);
+use crate::AnpassError;
+
+/// A force constant in the fort.9903 format: the 1-based indices of the
+/// variables it is a derivative with respect to, padded with zeros, and its
+/// value
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Fc {
+    pub indices: Vec<usize>,
+    pub value: f64,
+}
+
+impl Fc {
+    /// construct a force constant with the four indices used for surfaces up
+    /// to fourth order
+    ///
+    /// ```
+    /// use rust_anpass::fc::Fc;
+    /// let fc = Fc::new(2, 1, 0, 0, 0.364250381719);
+    /// assert_eq!(fc, Fc::from_indices(vec![2, 1, 0, 0], 0.364250381719));
+    /// ```
+    pub fn new(a: usize, b: usize, c: usize, d: usize, value: f64) -> Self {
+        Self::from_indices(vec![a, b, c, d], value)
+    }
+
+    /// construct a force constant with any number of indices, as needed for
+    /// fifth and higher order force constants
+    pub fn from_indices(indices: Vec<usize>, value: f64) -> Self {
+        Self { indices, value }
+    }
+
+    /// return the derivative indices of the force constant. unused indices
+    /// are zero
+    ///
+    /// ```
+    /// use rust_anpass::fc::Fc;
+    /// let fc = Fc::new(2, 1, 0, 0, 0.364250381719);
+    /// assert_eq!(fc.indices(), [2, 1, 0, 0]);
+    /// ```
+    pub fn indices(&self) -> &[usize] {
+        &self.indices
+    }
+
+    /// return the value of the force constant
+    ///
+    /// ```
+    /// use rust_anpass::fc::Fc;
+    /// let fc = Fc::new(2, 1, 0, 0, 0.364250381719);
+    /// assert_eq!(fc.value(), 0.364250381719);
+    /// ```
+    pub fn value(&self) -> f64 {
+        self.value
+    }
+
+    /// return the order of the force constant, the number of nonzero indices
+    ///
+    /// ```
+    /// use rust_anpass::fc::Fc;
+    /// assert_eq!(Fc::new(0, 0, 0, 0, 1e-12).order(), 0);
+    /// assert_eq!(Fc::new(2, 1, 0, 0, 0.364250381719).order(), 2);
+    /// assert_eq!(Fc::new(3, 3, 1, 1, 8.562725561910).order(), 4);
+    /// assert_eq!(Fc::from_indices(vec![1; 5], 1.0).order(), 5);
+    /// ```
+    pub fn order(&self) -> usize {
+        self.indices().iter().filter(|&&i| i != 0).count()
+    }
+}
+
+/// count the force constants in `fcs` by their order, as determined by
+/// `Fc::order`
+pub fn fc_counts(fcs: &[Fc]) -> BTreeMap<usize, usize> {
+    let mut ret = BTreeMap::new();
+    for fc in fcs {
+        *ret.entry(fc.order()).or_default() += 1;
+    }
+    ret
+}
+
+/// return a name for force constants of order `order`
+fn order_name(order: usize) -> String {
+    match order {
+        0 => "Constant".to_owned(),
+        1 => "Linear".to_owned(),
+        2 => "Quadratic".to_owned(),
+        3 => "Cubic".to_owned(),
+        4 => "Quartic".to_owned(),
+        n => format!("Order {n}"),
+    }
+}
+
+/// format `fcs` as a LaTeX `tabular` with one column for each index, at least
+/// four, and one for the value, printed with `precision` decimal places. The
+/// force constants are grouped by order, with a heading row for each group
+pub fn fcs_to_latex(fcs: &[Fc], precision: usize) -> String {
+    use std::fmt::Write;
+    let mut groups: BTreeMap<usize, Vec<&Fc>> = BTreeMap::new();
+    for fc in fcs {
+        groups.entry(fc.order()).or_default().push(fc);
+    }
+    let nidx = fcs.iter().map(|fc| fc.indices().len()).max().unwrap_or(0);
+    let nidx = nidx.max(4);
+    let mut s = String::new();
+    writeln!(s, "\\begin{{tabular}}{{{}r}}", "c".repeat(nidx)).unwrap();
+    writeln!(s, "\\hline").unwrap();
+    for name in ('i'..).take(nidx) {
+        write!(s, "${name}$ & ").unwrap();
+    }
+    writeln!(s, "Value \\\\").unwrap();
+    writeln!(s, "\\hline").unwrap();
+    for (order, group) in groups {
+        writeln!(
+            s,
+            "\\multicolumn{{{}}}{{l}}{{{}}} \\\\",
+            nidx + 1,
+            order_name(order)
+        )
+        .unwrap();
+        for fc in group {
+            for k in 0..nidx {
+                match fc.indices().get(k) {
+                    Some(&i) if i != 0 => write!(s, "{i} & ").unwrap(),
+                    _ => write!(s, " & ").unwrap(),
+                }
+            }
+            writeln!(s, "{:.*} \\\\", precision, fc.value()).unwrap();
+        }
+    }
+    writeln!(s, "\\hline").unwrap();
+    writeln!(s, "\\end{{tabular}}").unwrap();
+    s
+}
+
+/// read force constants in the fort.9903 format written by
+/// `Anpass::write9903` from `r`. Blank lines are skipped
+pub fn read9903<R: BufRead>(r: R) -> Result<Vec<Fc>, AnpassError> {
+    let mut ret = Vec::new();
+    for (i, line) in r.lines().enumerate() {
+        let line = line.map_err(|e| {
+            AnpassError::Other(format!(
+                "failed to read line {} with {e}",
+                i + 1
+            ))
+        })?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fc = line.parse().map_err(|_| {
+            AnpassError::Other(format!(
+                "failed to parse force constant on line {}: {line}",
+                i + 1
+            ))
+        })?;
+        ret.push(fc);
+    }
+    Ok(ret)
+}
+
+/// compare the force constants in `a` and `b` pairwise by position, returning
+/// the pairs whose indices differ or whose values differ by more than `tol`,
+/// along with the absolute difference in their values. If the slices differ in
+/// length, only their common prefix is compared
+pub fn compare9903(a: &[Fc], b: &[Fc], tol: f64) -> Vec<(Fc, Fc, f64)> {
+    a.iter()
+        .zip(b)
+        .filter_map(|(x, y)| {
+            let diff = (x.value() - y.value()).abs();
+            if x.indices() != y.indices() || diff > tol {
+                Some((x.clone(), y.clone(), diff))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// compute the 64-bit [FNV-1a](https://en.wikipedia.org/wiki/Fowler%E2%80%93Noll%E2%80%93Vo_hash_function)
+/// hash of `data`. This is the checksum returned by
+/// `Anpass::write9903_checksum`, so applying it to the contents of a written
+/// fort.9903 file verifies its integrity
+pub fn checksum(data: &[u8]) -> u64 {
+    fnv1a(FNV_OFFSET, data)
+}
+
+fn fnv1a(mut hash: u64, data: &[u8]) -> u64 {
+    for &b in data {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+/// a `Write` adapter that computes a running `checksum` of everything written
+/// through it
+pub(crate) struct ChecksumWriter<'a, W: Write> {
+    inner: &'a mut W,
+    hash: u64,
+}
+
+impl<'a, W: Write> ChecksumWriter<'a, W> {
+    pub(crate) fn new(inner: &'a mut W) -> Self {
+        Self {
+            inner,
+            hash: FNV_OFFSET,
+        }
+    }
+
+    pub(crate) fn checksum(&self) -> u64 {
+        self.hash
+    }
+}
+
+impl<W: Write> Write for ChecksumWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hash = fnv1a(self.hash, &buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
 
 impl FromStr for Fc {
     type Err = std::io::Error;
 
+    /// parse a line of fort.9903: at least one index followed by the value
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let s = s.split_whitespace().collect::<Vec<_>>();
-        let e = Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            "failed to parse Fc from string",
-        ));
-        if s.len() != 5 {
-            e
-        } else {
-            let us = s[..4].iter().map(|s| s.parse::<usize>());
-            if us.clone().any(|s| s.is_err()) {
-                return e;
-            }
-            let us = us.flatten().collect::<Vec<_>>();
-            let f = match s[4].parse::<f64>() {
-                Ok(f) => f,
-                Err(_) => return e,
-            };
-            Ok(Fc(us[0], us[1], us[2], us[3], f))
+        let e = || std::io::Error::other("failed to parse Fc from string");
+        let Some((value, indices)) = s.split_last() else {
+            return Err(e());
+        };
+        if indices.is_empty() {
+            return Err(e());
         }
+        let indices = indices
+            .iter()
+            .map(|s| s.parse::<usize>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| e())?;
+        let value = value.parse::<f64>().map_err(|_| e())?;
+        Ok(Fc::from_indices(indices, value))
     }
 }
 
@@ -38,25 +263,48 @@ impl approx::AbsDiffEq for Fc {
     }
 
     fn abs_diff_eq(&self, other: &Self, epsilon: Self::Epsilon) -> bool {
-        let ret = self.0 == other.0
-            && self.1 == other.1
-            && self.2 == other.2
-            && self.3 == other.3
-            && self.4.abs_diff_eq(&other.4, epsilon);
+        let ret = self.indices == other.indices
+            && self.value.abs_diff_eq(&other.value, epsilon);
         if !ret {
-            eprintln!("diff = {:.6e}", self.4 - other.4);
+            eprintln!("diff = {:.6e}", self.value - other.value);
         }
         ret
     }
 }
 
+/// the default number of digits after the decimal point in the values of
+/// force constants written by `Fc`'s `Display` implementation
+pub const DEFAULT_PRECISION: usize = 12;
+
+/// the width of the field for each index written by `Fc`'s `Display`
+/// implementation
+pub const INDEX_WIDTH: usize = 5;
+
+/// the number of characters by which the field for the value written by
+/// `Fc`'s `Display` implementation exceeds its precision, leaving room for the
+/// sign, the integer part, the decimal point, and spaces separating it from
+/// the indices
+pub const VALUE_EXTRA_WIDTH: usize = 8;
+
+/// Write the indices in fields of width `INDEX_WIDTH` followed by the value.
+/// The value is written with the precision of the formatter, or
+/// `DEFAULT_PRECISION` if none is given, in a field `VALUE_EXTRA_WIDTH` wider
+/// than the precision. Lines written with the default precision are parsed
+/// back to the same `Fc` by the `FromStr` implementation
+///
+/// ```
+/// use rust_anpass::fc::Fc;
+/// let fc = Fc::new(2, 1, 0, 0, 0.364250381719);
+/// assert_eq!(fc.to_string(), "    2    1    0    0      0.364250381719");
+/// assert_eq!(format!("{fc:.3}"), "    2    1    0    0      0.364");
+/// ```
 impl Display for Fc {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{:5}{:5}{:5}{:5}{:20.12}",
-            self.0, self.1, self.2, self.3, self.4,
-        )?;
+        for i in &self.indices {
+            write!(f, "{i:INDEX_WIDTH$}")?;
+        }
+        let prec = f.precision().unwrap_or(DEFAULT_PRECISION);
+        write!(f, "{:w$.prec$}", self.value, w = prec + VALUE_EXTRA_WIDTH)?;
         Ok(())
     }
 }
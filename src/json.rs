@@ -0,0 +1,113 @@
+//! JSON export and import of an `Anpass`, available with the `json` feature
+
+use serde::{Deserialize, Serialize};
+
+use crate::{na, Anpass, AnpassError, Bias, Dvec};
+
+/// The JSON representation of an `Anpass`. Unlike the derived `Serialize`
+/// implementation, which uses nalgebra's column-major layout, every matrix is
+/// written as an array of rows so that the output is easy to consume from
+/// other languages
+#[derive(Serialize, Deserialize)]
+struct JsonAnpass {
+    /// one row per data point, one column per variable
+    disps: Vec<Vec<f64>>,
+    /// one entry per data point, or empty for a template without energies
+    energies: Vec<f64>,
+    /// one row per variable, one column per term of the polynomial
+    exponents: Vec<Vec<i32>>,
+    #[serde(default)]
+    bias: Option<JsonBias>,
+    #[serde(default)]
+    weights: Option<Vec<f64>>,
+    /// one row per data point, one column per surface
+    #[serde(default)]
+    surfaces: Option<Vec<Vec<f64>>>,
+    #[serde(default)]
+    header: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonBias {
+    disp: Vec<f64>,
+    energy: f64,
+}
+
+fn to_rows<T: na::Scalar + Copy>(m: &na::DMatrix<T>) -> Vec<Vec<T>> {
+    m.row_iter().map(|r| r.iter().cloned().collect()).collect()
+}
+
+/// convert `rows` to a matrix, or return an error naming the `field` if the
+/// rows have different lengths. `ncols` is used for the shape of an empty
+/// matrix
+fn from_rows<T: na::Scalar + Copy>(
+    rows: &[Vec<T>],
+    ncols: usize,
+    field: &str,
+) -> Result<na::DMatrix<T>, AnpassError> {
+    let ncols = rows.first().map_or(ncols, Vec::len);
+    if rows.iter().any(|r| r.len() != ncols) {
+        return Err(AnpassError::Other(format!(
+            "rows of `{field}` have different lengths"
+        )));
+    }
+    Ok(na::DMatrix::from_row_iterator(
+        rows.len(),
+        ncols,
+        rows.iter().flatten().cloned(),
+    ))
+}
+
+impl Anpass {
+    /// serialize `self` to JSON. The result is an object with the fields
+    /// `disps`, an array of rows with one row per data point; `energies`, an
+    /// array with one entry per data point; `exponents`, an array of rows with
+    /// one row per variable and one column per term; `bias`, either null or an
+    /// object with the fields `disp`, an array, and `energy`; `weights`,
+    /// either null or an array; `surfaces`, either null or an array of rows
+    /// like `disps`; and `header`, a string
+    pub fn to_json(&self) -> String {
+        let json = JsonAnpass {
+            disps: to_rows(&self.disps),
+            energies: self.energies.iter().cloned().collect(),
+            exponents: to_rows(&self.exponents),
+            bias: self.bias.as_ref().map(|b| JsonBias {
+                disp: b.disp.iter().cloned().collect(),
+                energy: b.energy,
+            }),
+            weights: self.weights.as_ref().map(|w| w.iter().cloned().collect()),
+            surfaces: self.surfaces.as_ref().map(to_rows),
+            header: self.header.clone(),
+        };
+        serde_json::to_string(&json).unwrap()
+    }
+
+    /// deserialize an `Anpass` from the JSON written by `to_json`. `bias`,
+    /// `weights`, `surfaces`, and `header` may be omitted. The result is
+    /// constructed with `Anpass::builder`, so mismatched dimensions return
+    /// the same errors as `AnpassBuilder::build`
+    pub fn from_json(s: &str) -> Result<Self, AnpassError> {
+        let json: JsonAnpass = serde_json::from_str(s).map_err(|e| {
+            AnpassError::Other(format!("failed to parse JSON with {e}"))
+        })?;
+        let nvbl = json.exponents.len();
+        let mut builder = Anpass::builder()
+            .disps(from_rows(&json.disps, nvbl, "disps")?)
+            .energies(Dvec::from(json.energies))
+            .exponents(from_rows(&json.exponents, 0, "exponents")?)
+            .header(json.header);
+        if let Some(b) = json.bias {
+            builder = builder.bias(Bias {
+                disp: Dvec::from(b.disp),
+                energy: b.energy,
+            });
+        }
+        if let Some(w) = json.weights {
+            builder = builder.weights(Dvec::from(w));
+        }
+        if let Some(s) = json.surfaces {
+            builder = builder.surfaces(from_rows(&s, 0, "surfaces")?);
+        }
+        builder.build()
+    }
+}
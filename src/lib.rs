@@ -6,10 +6,13 @@ use std::fmt::Debug;
 use std::fmt::Display;
 use std::io::BufRead;
 use std::io::BufReader;
-use std::io::Read;
 use std::io::Write;
+use std::str::FromStr;
 
 pub mod fc;
+#[cfg(feature = "json")]
+mod json;
+mod stats;
 
 #[cfg(test)]
 mod tests;
@@ -25,9 +28,19 @@ pub type Dmat = na::DMatrix<f64>;
 pub type Dvec = na::DVector<f64>;
 
 #[derive(Debug, PartialEq, Clone)]
-pub struct Bias {
-    pub disp: Dvec,
-    pub energy: f64,
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Bias<T: na::Scalar = f64> {
+    pub disp: na::DVector<T>,
+    pub energy: T,
+}
+
+impl<T: na::RealField + Copy> Bias<T> {
+    /// map the point `x` in the coordinates produced by `Anpass::bias` back
+    /// to the original displacement coordinates, such as for reporting a
+    /// stationary point found after biasing
+    pub fn unbias_point(&self, x: &na::DVector<T>) -> na::DVector<T> {
+        x + &self.disp
+    }
 }
 
 impl Default for Bias {
@@ -49,16 +62,156 @@ impl Display for Bias {
     }
 }
 
+/// A polynomial fitting problem: the displacements and energies of the data
+/// points and the exponents of the terms of the polynomial. The scalar type
+/// `T` of the displacements and energies defaults to `f64`, which every method
+/// supports. `fit`, `eval`, `grad`, `hess`, and the other methods in the
+/// generic `impl` block also work for other `na::RealField` types, such as
+/// `f32` to halve the storage of a large data set. The `Debug`, `Display`, and
+/// `FromStr` implementations are only provided for `Anpass<f64>`
 #[derive(Clone)]
-pub struct Anpass {
-    pub disps: Dmat,
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Anpass<T: na::Scalar = f64> {
+    pub disps: na::DMatrix<T>,
     /// empty if loaded from a template without energies, as determined by the
     /// documentation for `load`
-    pub energies: Dvec,
-    /// i32 for compatibility with `f64::powi`
+    pub energies: na::DVector<T>,
+    /// i32 for compatibility with `f64::powi`. Negative exponents, such as
+    /// the 1/r terms of a long-range tail, are supported by `fit`, `eval`, and
+    /// the derivative methods but not by `make9903`, since they have no
+    /// force-constant interpretation
     pub exponents: na::DMatrix<i32>,
     ///  empty if not running at a stationary point
-    pub bias: Option<Bias>,
+    pub bias: Option<Bias<T>>,
+    /// per-point weights for weighted least squares, read from the `WEIGHTS`
+    /// section of the input. empty for an ordinary, unweighted fit
+    pub weights: Option<na::DVector<T>>,
+    /// every energy column, one per surface, when the format line declares
+    /// more than one, as in `(3F12.8,2f20.12)`. The first column is also
+    /// stored in `energies`, and the methods that change the energies, such
+    /// as `bias`, apply the same change to every surface. See `fit_multi`
+    pub surfaces: Option<na::DMatrix<T>>,
+    /// the lines preceding the format line in the input, reproduced by the
+    /// `Display` implementation with the counts following `INDEPENDENT
+    /// VARIABLES` and `DATA POINTS` updated to match the current data. empty
+    /// if not loaded from an input file
+    #[cfg_attr(feature = "serde", serde(default))]
+    header: String,
+}
+
+/// The displacements, energies, and exponents of an `Anpass` in plain `Vec`s,
+/// for interoperating with code that does not depend on nalgebra. Each inner
+/// `Vec` is one row of the corresponding matrix
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlainAnpass {
+    pub disps: Vec<Vec<f64>>,
+    pub energies: Vec<f64>,
+    pub exponents: Vec<Vec<i32>>,
+}
+
+/// A builder for constructing an `Anpass` in code rather than loading it from
+/// an input file, as returned by `Anpass::builder`. `disps` and `exponents`
+/// are required, while `energies` may be omitted to build a template without
+/// energies
+#[derive(Clone, Debug)]
+pub struct AnpassBuilder<T: na::Scalar = f64> {
+    disps: Option<na::DMatrix<T>>,
+    energies: Option<na::DVector<T>>,
+    exponents: Option<na::DMatrix<i32>>,
+    bias: Option<Bias<T>>,
+    weights: Option<na::DVector<T>>,
+    surfaces: Option<na::DMatrix<T>>,
+    header: String,
+}
+
+/// Summary statistics of the energies in an `Anpass`, as returned by
+/// `Anpass::energy_stats`
+#[derive(Clone, Debug, PartialEq)]
+pub struct EnergyStats {
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub median: f64,
+    /// the population standard deviation
+    pub std_dev: f64,
+}
+
+/// The result of a least-squares fit, as returned by `Anpass::fit_full`
+#[derive(Clone, Debug, PartialEq)]
+pub struct FitResult<T: na::Scalar = f64> {
+    /// the fitted coefficients, one for each column of `Anpass::exponents`
+    pub coeffs: na::DVector<T>,
+    /// the residual of each data point, observed minus computed
+    pub residuals: na::DVector<T>,
+    /// the root-mean-square deviation of the residuals
+    pub rmsd: T,
+    /// the design matrix X, with one row per data point and one column per
+    /// unknown, as returned by `Anpass::build_design_matrix`
+    pub x: na::DMatrix<T>,
+}
+
+/// Goodness-of-fit measures for a set of coefficients, as returned by
+/// `Anpass::fit_statistics`
+#[derive(Clone, Debug, PartialEq)]
+pub struct FitStats {
+    /// the root-mean-square deviation of the residuals
+    pub rmsd: f64,
+    pub max_abs_residual: f64,
+    /// the coefficient of determination, 1 - SSᵣₑₛ/SSₜₒₜ
+    pub r_squared: f64,
+    pub n_points: usize,
+}
+
+/// A record of the steps taken by `run`: the stationary point of the initial
+/// fit, and the coefficients and residuals of the refitting about it, as
+/// returned by `Anpass::run_report`. The `Display` implementation writes the
+/// stationary point followed by the point-by-point residuals, in the format of
+/// the `report.txt` written by `Anpass::dump_run`
+#[derive(Debug, PartialEq)]
+pub struct FitReport {
+    /// the kind of stationary point found by the Newton search
+    pub kind: StatKind,
+    /// the stationary point and its energy, subtracted from the data before
+    /// refitting
+    pub bias: Bias,
+    /// the refitted coefficients
+    pub coeffs: Dvec,
+    /// the energy of each biased data point computed from `coeffs`
+    pub computed: Dvec,
+    /// the biased energy of each data point
+    pub observed: Dvec,
+    /// the sum of squared residuals of the refitting
+    pub sum_squared_residuals: f64,
+}
+
+impl Display for FitReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "STATIONARY POINT IS A {}", self.kind)?;
+        writeln!(f, "WHERE ENERGY IS {:20.12}", self.bias.energy)?;
+        for c in &self.bias.disp {
+            writeln!(f, "{c:18.10}")?;
+        }
+        fmt_residuals(f, &self.computed, &self.observed)?;
+        Ok(())
+    }
+}
+
+/// write the index, `computed` and `observed` values, and residual of each
+/// data point to `w` in the format of the Fortran anpass, followed by the sum
+/// of squared residuals, which is also returned
+fn fmt_residuals<W: std::fmt::Write>(
+    w: &mut W,
+    computed: &Dvec,
+    observed: &Dvec,
+) -> Result<f64, std::fmt::Error> {
+    let mut sum = 0.0;
+    for (i, (comp, obsv)) in computed.iter().zip(observed.iter()).enumerate() {
+        let resi = comp - obsv;
+        writeln!(w, "{:5}{:20.12}{:20.12}{:20.8e}", i + 1, comp, obsv, resi)?;
+        sum += resi * resi;
+    }
+    writeln!(w, "WEIGHTED SUM OF SQUARED RESIDUALS IS {sum:17.8e}")?;
+    Ok(sum)
 }
 
 impl Debug for Anpass {
@@ -66,7 +219,10 @@ impl Debug for Anpass {
         write!(f, "disps:\n{:12.8}", self.disps)?;
         write!(f, "energies:\n{:20.12}", self.energies)?;
         write!(f, "exponents:\n{:5}", self.exponents)?;
-        write!(f, "bias:\n{:?}", self.bias)
+        write!(f, "bias:\n{:?}", self.bias)?;
+        write!(f, "weights:\n{:?}", self.weights)?;
+        write!(f, "surfaces:\n{:?}", self.surfaces)?;
+        write!(f, "header:\n{}", self.header)
     }
 }
 
@@ -78,58 +234,65 @@ impl PartialEq for Anpass {
             && self.energies.abs_diff_eq(&other.energies, 1e-11)
             && self.exponents.eq(&other.exponents)
             && self.bias.eq(&other.bias)
+            && self.weights.eq(&other.weights)
+            && self.surfaces.eq(&other.surfaces)
+            && self.header.eq(&other.header)
     }
 }
 
 impl Display for Anpass {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        writeln!(
-            f,
-            "!INPUT
-TITLE
-from rust-anpass by BRW
-INDEPENDENT VARIABLES"
-        )?;
-        let (rows, cols) = self.disps.shape();
-        writeln!(f, "{cols:4}")?;
-        writeln!(
-            f,
-            "DATA POINTS
-{:5}{:5}",
-            rows, -2
-        )?;
-        writeln!(f, "({cols}F12.8,f20.12)")?;
-        for row in 0..rows {
-            for col in 0..cols {
-                write!(f, "{:12.8}", self.disps[(row, col)])?;
-            }
-            writeln!(f, "{:20.12}", self.energies[row])?;
-        }
-        writeln!(f, "UNKNOWNS")?;
-        let (rows, cols) = self.exponents.shape();
-        writeln!(f, "{cols:4}")?;
-        writeln!(f, "FUNCTION")?;
-        for row in 0..rows {
-            for col in 0..cols {
-                if col > 0 && col % 16 == 0 {
-                    writeln!(f)?;
-                }
-                write!(f, "{:5}", self.exponents[(row, col)])?;
-            }
-            writeln!(f)?;
-        }
-        writeln!(
-            f,
-            "END OF DATA
-!FIT
-!STATIONARY POINT
-!END"
-        )?;
-        Ok(())
+        self.fmt_input(f, 12, 8, 12)
+    }
+}
+
+impl FromStr for Anpass {
+    type Err = AnpassError;
+
+    /// parse an Anpass from the contents of an input file, exactly as in
+    /// `load`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse_lines(s.lines())
+    }
+}
+
+/// A fitted polynomial, holding only the exponents and coefficients needed to
+/// evaluate it and its derivatives, without the data used to fit it. See
+/// `Anpass::into_polynomial`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Polynomial<T = f64> {
+    /// one row per variable and one column per term, as in `Anpass::exponents`
+    pub exponents: na::DMatrix<i32>,
+    /// one coefficient per term
+    pub coeffs: na::DVector<T>,
+}
+
+impl<T: na::RealField + Copy> Polynomial<T> {
+    /// the number of variables the polynomial depends on
+    pub fn n_variables(&self) -> usize {
+        self.exponents.nrows()
+    }
+
+    /// evaluate the polynomial at the point `x`, exactly as in `Anpass::eval`
+    pub fn eval(&self, x: &na::DVector<T>) -> T {
+        poly_eval(&self.exponents, x.iter(), &self.coeffs)
+    }
+
+    /// compute the gradient of the polynomial at `x`, exactly as in
+    /// `Anpass::grad`
+    pub fn grad(&self, x: &na::DVector<T>) -> na::DVector<T> {
+        poly_grad(&self.exponents, x, &self.coeffs)
+    }
+
+    /// compute the Hessian of the polynomial at `x`, exactly as in
+    /// `Anpass::hess`
+    pub fn hess(&self, x: &na::DVector<T>) -> na::DMatrix<T> {
+        poly_hess(&self.exponents, x, &self.coeffs)
     }
 }
 
 #[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum StatKind {
     Max,
     Min,
@@ -150,101 +313,326 @@ impl Display for StatKind {
     }
 }
 
-#[derive(Debug)]
-pub struct AnpassError(pub String);
+/// The errors returned by the fallible methods of `Anpass`. New variants may be
+/// added in minor releases, so matches on this enum need a wildcard arm
+#[derive(Debug, PartialEq)]
+#[non_exhaustive]
+pub enum AnpassError {
+    /// a general error described only by its message
+    Other(String),
+    /// the number of displacement fields declared in the format line does not
+    /// match the number of fields in the first displacement row, on the
+    /// 1-based input `line`
+    FormatFieldMismatch {
+        line: usize,
+        declared: usize,
+        found: usize,
+    },
+    /// the stationary point found was not a minimum, but a minimum was
+    /// required
+    WrongStationaryType { found: StatKind },
+    /// the input file `filename` could not be opened
+    Open { filename: String, msg: String },
+    /// the input did not contain a format line like `(3F12.8,f20.12)`
+    MissingFormatLine,
+    /// `field` on the 1-based input `line` could not be parsed as a number
+    InvalidField { line: usize, field: String },
+    /// the row of displacements, exponents, or stationary-point coordinates
+    /// ending on the 1-based input `line` contains `found` fields, but
+    /// `expected` were required. For displacements, `expected` is the width of
+    /// the first row
+    RowWidth {
+        line: usize,
+        expected: usize,
+        found: usize,
+    },
+    /// the search for a stationary point failed
+    Newton(NewtonError),
+}
 
-impl Anpass {
-    pub fn load_file(filename: &str) -> Self {
-        let f = match std::fs::File::open(filename) {
-            Ok(f) => f,
-            Err(e) => panic!("failed to open {filename} with {e}"),
-        };
-        Self::load(f)
-    }
-
-    /// Load an Anpass from `filename`. Everything before a line like
-    /// `(3F12.8,f20.12)` is ignored. This line signals the start of the
-    /// displacements. If the number of formats given in this line matches the
-    /// number of fields in each displacement line, the last field is treated as
-    /// an energy. Otherwise, every field is treated as a displacement
-    pub fn load(r: impl Read) -> Self {
-        let lines = BufReader::new(r).lines().map_while(Result::ok);
-        let start =
-            Regex::new(r"(?i)^\s*\((\d+)f[0-9.]+,f[0-9.]+\)\s*$").unwrap();
-        let mut ndisp_fields = usize::default();
-        #[derive(PartialEq)]
-        enum State {
-            Disp,
-            Exps,
-            Unks,
-            Stat,
-            None,
-        }
-        use State::*;
-        let mut state = None;
-        let mut disps = Vec::new();
-        let mut ndisps = 0;
-        let mut energies = Vec::new();
-        let mut nunk = usize::default();
-        let mut exponents = Vec::new();
-        let mut bias = std::option::Option::None;
-        for line in lines {
-            if start.is_match(&line) {
-                ndisp_fields =
-                    start.captures(&line).unwrap()[1].parse().unwrap();
-                state = Disp;
-            } else if line.contains("UNKNOWNS") {
-                state = Unks;
-            } else if line.contains("STATIONARY POINT")
-                && !line.starts_with('!')
-            {
-                state = Stat;
-            } else if state == Disp {
-                let f = line
-                    .split_whitespace()
-                    .flat_map(|s| s.parse::<f64>())
-                    .collect::<Vec<_>>();
-                let fl = f.len() - 1;
-                if fl == ndisp_fields {
-                    // disps + energy
-                    disps.extend_from_slice(&f[..fl]);
-                    energies.push(f[fl]);
-                } else {
-                    // only disps
-                    disps.extend(f);
-                }
-                ndisps += 1;
-            } else if state == Unks {
-                nunk = line.trim().parse().unwrap();
-                state = Exps;
-            } else if state == Exps {
-                exponents.extend(
-                    line.split_whitespace().flat_map(|s| s.parse::<i32>()),
-                );
-            } else if state == Stat {
-                let line = line
-                    .split_whitespace()
-                    .flat_map(|s| s.parse::<f64>())
-                    .collect::<Vec<_>>();
-                let l = line.len();
-                bias = Some(Bias {
-                    disp: Dvec::from(line[..l - 1].to_vec()),
-                    energy: line[l - 1],
-                });
-                state = None;
+impl Display for AnpassError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AnpassError::Other(s) => write!(f, "{s}"),
+            AnpassError::FormatFieldMismatch {
+                line,
+                declared,
+                found,
+            } => write!(
+                f,
+                "format line declares {declared} displacement fields, but \
+                 line {line} contains {found} fields"
+            ),
+            AnpassError::WrongStationaryType { found } => {
+                write!(f, "expected a minimum but found a {found}")
+            }
+            AnpassError::Open { filename, msg } => {
+                write!(f, "failed to open {filename} with {msg}")
+            }
+            AnpassError::MissingFormatLine => {
+                write!(f, "no format line found in input")
             }
+            AnpassError::InvalidField { line, field } => {
+                write!(f, "failed to parse `{field}` on line {line}")
+            }
+            AnpassError::RowWidth {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "row ending on line {line} contains {found} fields, but \
+                 {expected} were expected"
+            ),
+            AnpassError::Newton(e) => write!(f, "{e}"),
         }
+    }
+}
+
+impl std::error::Error for AnpassError {}
+
+impl From<NewtonError> for AnpassError {
+    fn from(e: NewtonError) -> Self {
+        Self::Newton(e)
+    }
+}
+
+/// Settings for the Newton iterations in `Anpass::newton_with`. The `Default`
+/// matches the settings used by `Anpass::newton`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NewtonOptions {
+    /// the maximum number of iterations before giving up
+    pub max_iter: usize,
+    /// the iterations have converged when every component of the step is at
+    /// most `tol` in magnitude
+    pub tol: f64,
+    /// the fraction of the full Newton step, -H⁻¹g, taken on each iteration
+    pub step_scale: f64,
+    /// if true, backtrack from the step given by `step_scale`, halving it
+    /// until the norm of the gradient decreases, before accepting it. This
+    /// prevents overshooting on surfaces far from quadratic
+    pub line_search: bool,
+    /// how the step is computed from the Hessian
+    pub method: StepMethod,
+}
+
+/// The ways of computing the Newton step from the Hessian in
+/// `Anpass::newton_with`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum StepMethod {
+    /// invert the Hessian directly, by the Cholesky decomposition if it is
+    /// positive definite and by the LU decomposition otherwise
+    #[default]
+    Invert,
+    /// add μI to the Hessian, increasing μ until the shifted Hessian is
+    /// positive definite, as in the Levenberg-Marquardt method, and reducing
+    /// it again after each step. this always moves downhill, so it heads for
+    /// a minimum even where the Hessian is indefinite
+    LevenbergMarquardt,
+    /// diagonalize the Hessian and take the step V diag(1/λ) Vᵀ g, leaving
+    /// out the directions with negligible eigenvalues. this handles negative
+    /// eigenvalues, so it can converge to maxima and saddle points
+    Eigen,
+}
+
+impl Default for NewtonOptions {
+    fn default() -> Self {
         Self {
-            disps: Dmat::from_row_slice(ndisps, ndisp_fields, &disps),
-            energies: Dvec::from(energies),
-            exponents: na::DMatrix::from_row_slice(
-                exponents.len() / nunk,
-                nunk,
-                &exponents,
+            max_iter: 100,
+            tol: 1.1e-8,
+            step_scale: 0.5,
+            line_search: false,
+            method: StepMethod::Invert,
+        }
+    }
+}
+
+/// A single iteration recorded by `Anpass::newton_with_history`
+#[derive(Clone, Debug, PartialEq)]
+pub struct NewtonStep {
+    /// the index of the iteration, starting from 0
+    pub iteration: usize,
+    /// the iterate at the start of the iteration
+    pub x: Dvec,
+    /// the norm of the gradient at `x`
+    pub grad_norm: f64,
+    /// the norm of the step taken from `x`. on the final iteration of a
+    /// converged run, this is the norm of the step that was small enough to
+    /// signal convergence, and it is not taken
+    pub step_norm: f64,
+}
+
+/// The ways `Anpass::try_newton` can fail to find a stationary point
+#[derive(Debug, PartialEq)]
+pub enum NewtonError {
+    /// the iteration limit was reached. `x` is the last iterate, and
+    /// `grad_norm` is the norm of the gradient there
+    NotConverged { x: Dvec, grad_norm: f64 },
+    /// the Hessian at `x` could not be inverted by the method selected in
+    /// `NewtonOptions::method`
+    SingularHessian { x: Dvec },
+}
+
+impl Display for NewtonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NewtonError::NotConverged { grad_norm, .. } => write!(
+                f,
+                "too many Newton iterations, final gradient norm {grad_norm:e}"
             ),
-            bias,
+            NewtonError::SingularHessian { .. } => {
+                write!(f, "singular Hessian in Newton iterations")
+            }
+        }
+    }
+}
+
+impl std::error::Error for NewtonError {}
+
+impl<T: na::Scalar> AnpassBuilder<T> {
+    /// set the displacements, with one row per data point and one column per
+    /// variable
+    pub fn disps(mut self, disps: na::DMatrix<T>) -> Self {
+        self.disps = Some(disps);
+        self
+    }
+
+    /// set the energies, with one entry per data point
+    pub fn energies(mut self, energies: na::DVector<T>) -> Self {
+        self.energies = Some(energies);
+        self
+    }
+
+    /// set the exponents, with one row per variable and one column per term of
+    /// the polynomial
+    pub fn exponents(mut self, exponents: na::DMatrix<i32>) -> Self {
+        self.exponents = Some(exponents);
+        self
+    }
+
+    /// set the bias applied before running at a stationary point
+    pub fn bias(mut self, bias: Bias<T>) -> Self {
+        self.bias = Some(bias);
+        self
+    }
+
+    /// set the per-point weights for a weighted least-squares fit
+    pub fn weights(mut self, weights: na::DVector<T>) -> Self {
+        self.weights = Some(weights);
+        self
+    }
+
+    /// set every energy column for `Anpass::fit_multi`, with one row per data
+    /// point and one column per surface. The first column must match
+    /// `energies`
+    pub fn surfaces(mut self, surfaces: na::DMatrix<T>) -> Self {
+        self.surfaces = Some(surfaces);
+        self
+    }
+
+    /// set the header written before the format line, exactly as in
+    /// `Anpass::with_header`
+    pub fn header(mut self, header: impl Into<String>) -> Self {
+        self.header = header.into();
+        if !self.header.is_empty() && !self.header.ends_with('\n') {
+            self.header.push('\n');
+        }
+        self
+    }
+
+    /// construct the `Anpass`, returning an error if `disps` or `exponents`
+    /// was not set or if the dimensions of the fields disagree. In particular,
+    /// `exponents` must have one row for each column of `disps`, `energies`
+    /// and `weights` must have one entry for each row of `disps`, and
+    /// `surfaces` must have one row for each row of `disps` and a first column
+    /// equal to `energies`
+    pub fn build(self) -> Result<Anpass<T>, AnpassError> {
+        let disps = self.disps.ok_or_else(|| {
+            AnpassError::Other("`disps` is required".to_string())
+        })?;
+        let exponents = self.exponents.ok_or_else(|| {
+            AnpassError::Other("`exponents` is required".to_string())
+        })?;
+        let energies = self
+            .energies
+            .unwrap_or_else(|| na::DVector::from_vec(Vec::new()));
+        let (npoints, nvbl) = disps.shape();
+        if exponents.nrows() != nvbl {
+            return Err(AnpassError::Other(format!(
+                "exponents have {} rows, expected {nvbl} to match the \
+                 columns of disps",
+                exponents.nrows()
+            )));
+        }
+        if !energies.is_empty() && energies.len() != npoints {
+            return Err(AnpassError::Other(format!(
+                "{} energies given for {npoints} displacements",
+                energies.len()
+            )));
+        }
+        if let Some(weights) = &self.weights {
+            if weights.len() != npoints {
+                return Err(AnpassError::Other(format!(
+                    "{} weights given for {npoints} displacements",
+                    weights.len()
+                )));
+            }
+        }
+        if let Some(bias) = &self.bias {
+            if bias.disp.len() != nvbl {
+                return Err(AnpassError::Other(format!(
+                    "bias has {} coordinates, expected {nvbl}",
+                    bias.disp.len()
+                )));
+            }
+        }
+        if let Some(surfaces) = &self.surfaces {
+            if surfaces.nrows() != npoints {
+                return Err(AnpassError::Other(format!(
+                    "surfaces have {} rows for {npoints} displacements",
+                    surfaces.nrows()
+                )));
+            }
+            if surfaces.ncols() == 0 || surfaces.column(0) != energies.column(0)
+            {
+                return Err(AnpassError::Other(
+                    "the first column of surfaces does not match energies"
+                        .to_string(),
+                ));
+            }
+        }
+        Ok(Anpass {
+            disps,
+            energies,
+            exponents,
+            bias: self.bias,
+            weights: self.weights,
+            surfaces: self.surfaces,
+            header: self.header,
+        })
+    }
+}
+
+impl<T: na::Scalar> Anpass<T> {
+    /// return an empty `AnpassBuilder` for constructing an `Anpass` in code
+    pub fn builder() -> AnpassBuilder<T> {
+        AnpassBuilder {
+            disps: None,
+            energies: None,
+            exponents: None,
+            bias: None,
+            weights: None,
+            surfaces: None,
+            header: String::new(),
         }
     }
+}
+
+impl<T: na::RealField + Copy> Anpass<T> {
+    /// return the number of independent variables in the fitted function
+    pub fn n_variables(&self) -> usize {
+        self.exponents.nrows()
+    }
 
     /// determine the [ordinary least
     /// squares](https://en.wikipedia.org/wiki/Ordinary_least_squares) solution
@@ -253,11 +641,128 @@ impl Anpass {
     /// described by `self.disps`, `self.energies`, and `self.exponents`, and
     /// return the solution vector along with the evaluated matrix describing
     /// the function. The latter is for checking the residuals. See the PDF
-    /// documentation for further details
-    pub fn fit(&self) -> (Dvec, Dmat) {
+    /// documentation for further details. If `self.weights` is present, the
+    /// weighted least-squares problem is solved instead, but the returned
+    /// matrix is still the unweighted one
+    pub fn fit(&self) -> (na::DVector<T>, na::DMatrix<T>) {
+        let FitResult { coeffs, x, .. } = self.fit_full();
+        (coeffs, x)
+    }
+
+    /// just like `fit`, but return the coefficients and design matrix along
+    /// with the residuals and their RMSD in a `FitResult`
+    pub fn fit_full(&self) -> FitResult<T> {
+        let (coeffs, x) = self.fit_with_design(None);
+        let residuals = &self.energies - &x * &coeffs;
+        let n: T = na::convert(residuals.len() as f64);
+        let rmsd = (residuals.norm_squared() / n).sqrt();
+        FitResult {
+            coeffs,
+            residuals,
+            rmsd,
+            x,
+        }
+    }
+
+    /// just like `fit`, but use the precomputed design matrix `x`, as returned
+    /// by `build_design_matrix`, if present. This saves rebuilding X when
+    /// fitting the same displacements repeatedly
+    pub fn fit_with_design(
+        &self,
+        x: Option<na::DMatrix<T>>,
+    ) -> (na::DVector<T>, na::DMatrix<T>) {
+        let x = x.unwrap_or_else(|| self.build_design_matrix());
+        if let Some(weights) = &self.weights {
+            return self.weighted(weights, x);
+        }
+        let y = &self.energies;
+        let xt = x.transpose();
+        let xtx = &xt * &x;
+        solve_least_squares(xtx, xt, y, x)
+    }
+
+    /// solve the weighted least-squares problem β = (XᵀWX)⁻¹XᵀWy, where W is
+    /// the diagonal matrix of `weights`, one for each data point, ignoring
+    /// `self.weights`. As in `fit`, the returned matrix is the unweighted X.
+    /// If every weight is one, this is exactly the unweighted `fit`. panics if
+    /// the number of weights does not match the number of points
+    pub fn fit_weighted(
+        &self,
+        weights: &na::DVector<T>,
+    ) -> (na::DVector<T>, na::DMatrix<T>) {
+        self.weighted(weights, self.build_design_matrix())
+    }
+
+    /// `fit_weighted` with the precomputed design matrix `x`
+    fn weighted(
+        &self,
+        weights: &na::DVector<T>,
+        x: na::DMatrix<T>,
+    ) -> (na::DVector<T>, na::DMatrix<T>) {
+        assert_eq!(
+            weights.len(),
+            x.nrows(),
+            "number of weights does not match number of points"
+        );
+        if weights.iter().all(|&w| w == T::one()) {
+            let xt = x.transpose();
+            let xtx = &xt * &x;
+            return solve_least_squares(xtx, xt, &self.energies, x);
+        }
+        // scaling the rows of X and y by √w gives XᵀWX and XᵀWy
+        let sqrtw = weights.map(T::sqrt);
+        let mut xw = x.clone();
+        for (mut row, w) in xw.row_iter_mut().zip(sqrtw.iter()) {
+            row *= *w;
+        }
+        let y = self.energies.component_mul(&sqrtw);
+        let xt = xw.transpose();
+        let xtx = &xt * &xw;
+        let (coeffs, _) = solve_least_squares(xtx, xt, &y, xw);
+        (coeffs, x)
+    }
+
+    /// build the design matrix X for the polynomial regression problem, where
+    /// each row corresponds to a displacement and each column to an unknown.
+    /// This is the matrix returned by `fit`, and it can be passed to the
+    /// `_with_design` methods to avoid rebuilding it
+    pub fn build_design_matrix(&self) -> na::DMatrix<T> {
+        #[cfg(feature = "rayon")]
+        return self.build_design_matrix_par();
+        #[cfg(not(feature = "rayon"))]
+        return self.build_design_matrix_serial();
+    }
+
+    /// compute every power of each variable that appears in `self.exponents`
+    /// once, up front. entry `j` of the result holds the smallest exponent
+    /// of variable `j` and an `ndisps x (max - min + 1)` matrix whose column
+    /// `e - min` contains `disps[(i, j)].powi(e)`, so the design matrix loops
+    /// can look up powers instead of recomputing them for every monomial
+    fn power_table(&self) -> Vec<(i32, na::DMatrix<T>)> {
+        let ndisps = self.disps.nrows();
+        self.exponents
+            .row_iter()
+            .zip(self.disps.column_iter())
+            .map(|(exps, col)| {
+                let lo = exps.iter().copied().min().unwrap_or(0).min(0);
+                let hi = exps.iter().copied().max().unwrap_or(0).max(0);
+                let table = na::DMatrix::from_fn(
+                    ndisps,
+                    (hi - lo + 1) as usize,
+                    |i, e| col[i].powi(e as i32 + lo),
+                );
+                (lo, table)
+            })
+            .collect()
+    }
+
+    /// the serial implementation of `build_design_matrix`
+    #[cfg(any(not(feature = "rayon"), test))]
+    fn build_design_matrix_serial(&self) -> na::DMatrix<T> {
         let (ndisps, ncols) = self.disps.shape();
         let (_, nunks) = self.exponents.shape();
-        let mut x = Dmat::repeat(ndisps, nunks, 1.0);
+        let powers = self.power_table();
+        let mut x = na::DMatrix::repeat(ndisps, nunks, T::one());
         for i in 0..ndisps {
             let row = self.disps.row(i);
             for k in 0..nunks {
@@ -265,111 +770,1179 @@ impl Anpass {
                 for j in 0..ncols {
                     let d = row[j];
                     let ejk = self.exponents[(j, k)];
-                    if (*xik != 0.0 || d != 0.0) && ejk != 0 {
-                        *xik *= d.powi(ejk);
+                    if (!xik.is_zero() || !d.is_zero()) && ejk != 0 {
+                        let (lo, table) = &powers[j];
+                        *xik *= table[(i, (ejk - lo) as usize)];
                     }
                 }
             }
         }
-        let y = &self.energies;
-        let xt = x.transpose();
-        let xtx = &xt * &x;
-        solve_least_squares(xtx, xt, y, x)
+        x
     }
 
-    /// compute the gradient of the function described by `coeffs` at `x`
-    fn grad(&self, x: &Dvec, coeffs: &Dvec) -> Dvec {
-        let (nvbl, nunk) = self.exponents.shape();
-        let mut grad = vec![0.0; nvbl];
-        for i in 0..nvbl {
-            let mut sum = 0.0;
-            for j in 0..nunk {
-                let fij = self.exponents[(i, j)];
-                let mut coj = coeffs[j] * fij as f64;
-                if coj.abs() < THR {
-                    continue;
-                }
-                if fij != 1 {
-                    coj *= x[i].powi(fij - 1);
-                }
-                for k in 0..nvbl {
-                    let ekj = self.exponents[(k, j)];
-                    if k != i && ekj != 0 {
-                        coj *= x[k].powi(ekj);
+    /// the original triple loop computing every power on the fly, kept as a
+    /// reference for `build_design_matrix_serial` and
+    /// `build_design_matrix_par`
+    #[cfg(test)]
+    fn build_design_matrix_naive(&self) -> na::DMatrix<T> {
+        let (ndisps, ncols) = self.disps.shape();
+        let (_, nunks) = self.exponents.shape();
+        let mut x = na::DMatrix::repeat(ndisps, nunks, T::one());
+        for i in 0..ndisps {
+            let row = self.disps.row(i);
+            for k in 0..nunks {
+                let xik = &mut x[(i, k)];
+                for j in 0..ncols {
+                    let d = row[j];
+                    let ejk = self.exponents[(j, k)];
+                    if (!xik.is_zero() || !d.is_zero()) && ejk != 0 {
+                        *xik *= d.powi(ejk);
                     }
                 }
-                sum += coj;
             }
-            grad[i] = sum;
         }
-        Dvec::from(grad)
+        x
     }
 
-    /// compute the hessian of the function described by `coeffs` at `x`
-    fn hess(&self, x: &Dvec, coeffs: &Dvec) -> Dmat {
-        let (nvbl, nunk) = self.exponents.shape();
-        let mut hess = Dmat::zeros(nvbl, nvbl);
-        for i in 0..nvbl {
-            for l in 0..=i {
-                let mut sum = 0.0;
-                if i != l {
-                    // off-diagonal
-                    for j in 0..nunk {
-                        let mut coj = coeffs[j];
-                        let eij = self.exponents[(i, j)];
-                        let elj = self.exponents[(l, j)];
-                        let fij = eij as f64;
-                        let flj = elj as f64;
-                        coj *= fij * flj;
-                        if coj.abs() < THR {
-                            continue;
-                        }
-                        if eij != 1 {
-                            coj *= x[i].powi(eij - 1);
-                        }
-                        if elj != 1 {
-                            coj *= x[l].powi(elj - 1);
-                        }
-                        for k in 0..nvbl {
-                            if k != i && k != l {
-                                let ekj = self.exponents[(k, j)];
-                                if ekj != 0 {
-                                    coj *= x[k].powi(ekj);
-                                }
-                            }
-                        }
-                        sum += coj;
+    /// the parallel implementation of `build_design_matrix`, enabled by the
+    /// `rayon` feature. Since nalgebra matrices are stored in column-major
+    /// order, each column of X is filled independently, which avoids a
+    /// transposing copy and keeps the reads of `self.disps` contiguous
+    #[cfg(feature = "rayon")]
+    fn build_design_matrix_par(&self) -> na::DMatrix<T> {
+        use rayon::prelude::*;
+        let ndisps = self.disps.nrows();
+        let (_, nunks) = self.exponents.shape();
+        let mut x = na::DMatrix::repeat(ndisps, nunks, T::one());
+        if ndisps == 0 {
+            return x;
+        }
+        let powers = self.power_table();
+        x.as_mut_slice()
+            .par_chunks_mut(ndisps)
+            .enumerate()
+            .for_each(|(k, col)| {
+                for (j, (lo, table)) in powers.iter().enumerate() {
+                    let ejk = self.exponents[(j, k)];
+                    if ejk == 0 {
+                        continue;
                     }
-                    hess[(i, l)] = sum;
-                    hess[(l, i)] = sum;
-                } else {
-                    // diagonal
-                    for j in 0..nunk {
-                        let mut coj = coeffs[j];
-                        let eij = self.exponents[(i, j)];
-                        let fij = eij as f64;
-                        coj *= fij * (fij - 1.);
-                        if coj.abs() < THR {
-                            continue;
+                    let pows = table.column((ejk - lo) as usize);
+                    for ((xik, &d), &p) in col
+                        .iter_mut()
+                        .zip(self.disps.column(j))
+                        .zip(pows.iter())
+                    {
+                        if !xik.is_zero() || !d.is_zero() {
+                            *xik *= p;
                         }
-                        if eij != 2 {
-                            coj *= x[i].powi(eij - 2);
-                        }
-                        for k in 0..nvbl {
-                            if k != i {
-                                let ekj = self.exponents[(k, j)];
-                                if ekj != 0 {
-                                    coj *= x[k].powi(ekj);
-                                }
-                            }
-                        }
-                        sum += coj;
                     }
-                    hess[(i, l)] = sum;
                 }
+            });
+        x
+    }
+
+    /// evaluate the function at the point `x`
+    pub fn eval(&self, x: &na::DVector<T>, coeffs: &na::DVector<T>) -> T {
+        poly_eval(&self.exponents, x.iter(), coeffs)
+    }
+
+    /// evaluate the function at each row of `points`, returning one energy
+    /// per row. useful for scanning a grid or a cut through the surface
+    pub fn eval_many(
+        &self,
+        points: &na::DMatrix<T>,
+        coeffs: &na::DVector<T>,
+    ) -> na::DVector<T> {
+        assert_eq!(
+            points.ncols(),
+            self.n_variables(),
+            "number of columns does not match number of variables"
+        );
+        na::DVector::from_iterator(
+            points.nrows(),
+            points
+                .row_iter()
+                .map(|row| poly_eval(&self.exponents, row.iter(), coeffs)),
+        )
+    }
+
+    /// compute the gradient of the function described by `coeffs` at `x`.
+    /// entry i is the derivative with respect to displacement variable i,
+    /// corresponding to column i of `disps` and row i of `exponents`
+    pub fn grad(
+        &self,
+        x: &na::DVector<T>,
+        coeffs: &na::DVector<T>,
+    ) -> na::DVector<T> {
+        poly_grad(&self.exponents, x, coeffs)
+    }
+
+    /// compute the hessian of the function described by `coeffs` at `x`.
+    /// entry (i, j) is the second derivative with respect to displacement
+    /// variables i and j, numbered as in `grad`, so the result is symmetric
+    pub fn hess(
+        &self,
+        x: &na::DVector<T>,
+        coeffs: &na::DVector<T>,
+    ) -> na::DMatrix<T> {
+        poly_hess(&self.exponents, x, coeffs)
+    }
+
+    /// consume `self`, keeping only the exponents, and combine them with the
+    /// fitted `coeffs` into a `Polynomial`. panics if there is not one
+    /// coefficient per term
+    pub fn into_polynomial(self, coeffs: na::DVector<T>) -> Polynomial<T> {
+        assert_eq!(
+            coeffs.len(),
+            self.exponents.ncols(),
+            "number of coefficients does not match number of terms"
+        );
+        Polynomial {
+            exponents: self.exponents,
+            coeffs,
+        }
+    }
+}
+
+impl Anpass {
+    /// load an Anpass from the file named by `filename`, as described in
+    /// `load`. panics if the file cannot be opened or parsed. See `try_load`
+    /// for a fallible version
+    pub fn load_file(filename: &str) -> Self {
+        Self::try_load(filename).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// the fallible version of `load_file`, returning an error if the file
+    /// cannot be opened, lacks a format line, contains a displacement,
+    /// weight, exponent, or stationary-point field that is not a number,
+    /// contains displacement rows of differing widths, or contains exponent
+    /// or stationary-point rows of the wrong width. With the `gzip` feature
+    /// enabled, files ending in `.gz` are decompressed transparently
+    pub fn try_load(filename: &str) -> Result<Self, AnpassError> {
+        let f =
+            std::fs::File::open(filename).map_err(|e| AnpassError::Open {
+                filename: filename.to_owned(),
+                msg: e.to_string(),
+            })?;
+        #[cfg(feature = "gzip")]
+        if filename.ends_with(".gz") {
+            return Self::parse(BufReader::new(flate2::read::GzDecoder::new(
+                f,
+            )));
+        }
+        Self::parse(BufReader::new(f))
+    }
+
+    /// Load an Anpass from the buffered reader `r`, such as a locked stdin, an
+    /// in-memory byte slice, or a decompressing reader. Everything before a
+    /// line like `(3F12.8,f20.12)` is stored in the header reproduced by
+    /// `Display` but otherwise ignored. This line signals the start of
+    /// the displacements. If the number of formats given in this line matches
+    /// the number of fields in each displacement line, the last field is
+    /// treated as an energy. Otherwise, every field is treated as a
+    /// displacement. A repeat count on the energy format, as in
+    /// `(3F12.8,2f20.12)`, declares that many trailing energy columns, which
+    /// are stored in `surfaces` for use by `fit_multi`.
+    ///
+    /// The exponents following the `FUNCTION` line are normally given as a
+    /// dense matrix with one row per variable and one column per unknown. If
+    /// the line instead reads `FUNCTION SPARSE`, each following line describes
+    /// one unknown as a list of 1-based `variable:power` pairs, such as `1:2
+    /// 3:1` for x₁²x₃, with a lone `0` denoting the constant term. Each
+    /// variable may appear at most once per line, and the number of lines must
+    /// match the count given after `UNKNOWNS`.
+    ///
+    /// A `WEIGHTS` line between the displacements and the `UNKNOWNS` section
+    /// begins an optional list of per-point weights, one for each
+    /// displacement, which are then used by `fit`. Weights must not be
+    /// negative.
+    ///
+    /// Lines whose first non-whitespace character is `#` or `!` are comments
+    /// and are ignored everywhere in the input.
+    ///
+    /// Panics if the input is invalid, such as when the number of fields in
+    /// the first displacement row does not match the format line
+    pub fn load(r: impl BufRead) -> Self {
+        Self::parse(r).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// the fallible implementation of `load`
+    fn parse(r: impl BufRead) -> Result<Self, AnpassError> {
+        Self::parse_lines(r.lines().map_while(Result::ok))
+    }
+
+    /// parse an Anpass from an iterator over the lines of its input, shared by
+    /// `parse` and the `FromStr` implementation
+    fn parse_lines<S: AsRef<str>>(
+        lines: impl Iterator<Item = S>,
+    ) -> Result<Self, AnpassError> {
+        let start = Regex::new(
+            r"(?i)^\s*\(\s*(\d+)\s*f[0-9.]+\s*,\s*(\d*)\s*f[0-9.]+\s*\)\s*$",
+        )
+        .unwrap();
+        let mut ndisp_fields = usize::default();
+        let mut nenergy_fields = 1;
+        #[derive(PartialEq)]
+        enum State {
+            Disp,
+            Exps,
+            Unks,
+            Sparse,
+            Weights,
+            Stat,
+            None,
+        }
+        use State::*;
+        let mut state = None;
+        let mut disps = Vec::new();
+        let mut ndisps = 0;
+        let mut energies = Vec::new();
+        let mut nunk = usize::default();
+        let mut exponents = Vec::new();
+        // the number of exponents read so far in the current row, which may
+        // be wrapped over several lines, and the line on which it ended
+        let mut row_len = 0;
+        let mut last_exp_line = 0;
+        let mut sparse = Vec::new();
+        let mut is_sparse = false;
+        let mut weights = Vec::new();
+        let mut surfaces = Vec::new();
+        let mut header = String::new();
+        let mut bias = std::option::Option::None;
+        let mut row_width = 0;
+        let mut found_format = false;
+        for (lineno, line) in lines.enumerate() {
+            let line = line.as_ref();
+            if !found_format && !start.is_match(line) {
+                header.push_str(line);
+                header.push('\n');
+                continue;
+            }
+            if line.trim_start().starts_with(['#', '!']) {
+                continue;
+            }
+            if start.is_match(line) {
+                let caps = start.captures(line).unwrap();
+                ndisp_fields = caps[1].parse().unwrap();
+                nenergy_fields = caps[2].parse().unwrap_or(1);
+                found_format = true;
+                state = Disp;
+            } else if line.contains("WEIGHTS") {
+                state = Weights;
+            } else if line.contains("UNKNOWNS") {
+                state = Unks;
+            } else if line.contains("STATIONARY POINT") {
+                state = Stat;
+            } else if line.contains("END OF DATA") {
+                state = None;
+            } else if state == Disp {
+                let f = line
+                    .split_whitespace()
+                    .map(|s| {
+                        parse_fortran_f64(s).map_err(|_| {
+                            AnpassError::InvalidField {
+                                line: lineno + 1,
+                                field: s.to_owned(),
+                            }
+                        })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                if row_width == 0 {
+                    if f.len() != ndisp_fields
+                        && f.len() != ndisp_fields + nenergy_fields
+                    {
+                        return Err(AnpassError::FormatFieldMismatch {
+                            line: lineno + 1,
+                            declared: ndisp_fields,
+                            found: f.len(),
+                        });
+                    }
+                    row_width = f.len();
+                } else if f.len() != row_width {
+                    return Err(AnpassError::RowWidth {
+                        line: lineno + 1,
+                        expected: row_width,
+                        found: f.len(),
+                    });
+                }
+                if f.len() > ndisp_fields {
+                    // disps + energies
+                    disps.extend_from_slice(&f[..ndisp_fields]);
+                    energies.push(f[ndisp_fields]);
+                    if nenergy_fields > 1 {
+                        surfaces.extend_from_slice(&f[ndisp_fields..]);
+                    }
+                } else {
+                    // only disps
+                    disps.extend(f);
+                }
+                ndisps += 1;
+            } else if state == Weights {
+                for s in line.split_whitespace() {
+                    // reject negative weights, which have no real square root
+                    // in the weighted fit
+                    let w = parse_fortran_f64(s)
+                        .ok()
+                        .filter(|w| *w >= 0.0)
+                        .ok_or_else(|| AnpassError::InvalidField {
+                            line: lineno + 1,
+                            field: s.to_owned(),
+                        })?;
+                    weights.push(w);
+                }
+            } else if state == Unks {
+                nunk = line.trim().parse().map_err(|_| {
+                    AnpassError::InvalidField {
+                        line: lineno + 1,
+                        field: line.trim().to_owned(),
+                    }
+                })?;
+                state = Exps;
+            } else if state == Exps && line.contains("FUNCTION") {
+                if line.contains("SPARSE") {
+                    state = Sparse;
+                    is_sparse = true;
+                }
+            } else if state == Sparse {
+                let line = line.trim();
+                if line == "0" {
+                    sparse.push(Vec::new());
+                } else if !line.is_empty() {
+                    let mut col: Vec<(usize, i32)> = Vec::new();
+                    for s in line.split_whitespace() {
+                        let err = || AnpassError::InvalidField {
+                            line: lineno + 1,
+                            field: s.to_owned(),
+                        };
+                        let (v, p) = s.split_once(':').ok_or_else(err)?;
+                        let v = v
+                            .parse::<usize>()
+                            .ok()
+                            .filter(|v| (1..=ndisp_fields).contains(v))
+                            .ok_or_else(err)?;
+                        let p = p.parse::<i32>().map_err(|_| err())?;
+                        if col.iter().any(|&(u, _)| u == v - 1) {
+                            return Err(err());
+                        }
+                        col.push((v - 1, p));
+                    }
+                    sparse.push(col);
+                }
+            } else if state == Exps {
+                for s in line.split_whitespace() {
+                    exponents.push(s.parse::<i32>().map_err(|_| {
+                        AnpassError::InvalidField {
+                            line: lineno + 1,
+                            field: s.to_owned(),
+                        }
+                    })?);
+                    row_len += 1;
+                }
+                if row_len > nunk {
+                    return Err(AnpassError::RowWidth {
+                        line: lineno + 1,
+                        expected: nunk,
+                        found: row_len,
+                    });
+                } else if row_len == nunk {
+                    row_len = 0;
+                }
+                last_exp_line = lineno + 1;
+            } else if state == Stat {
+                let f = line
+                    .split_whitespace()
+                    .map(|s| {
+                        parse_fortran_f64(s).map_err(|_| {
+                            AnpassError::InvalidField {
+                                line: lineno + 1,
+                                field: s.to_owned(),
+                            }
+                        })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                if f.len() != ndisp_fields + 1 {
+                    return Err(AnpassError::RowWidth {
+                        line: lineno + 1,
+                        expected: ndisp_fields + 1,
+                        found: f.len(),
+                    });
+                }
+                bias = Some(Bias {
+                    disp: Dvec::from(f[..ndisp_fields].to_vec()),
+                    energy: f[ndisp_fields],
+                });
+                state = None;
+            }
+        }
+        if !found_format {
+            return Err(AnpassError::MissingFormatLine);
+        }
+        if !weights.is_empty() && weights.len() != ndisps {
+            return Err(AnpassError::Other(format!(
+                "{} weights given for {ndisps} displacements",
+                weights.len()
+            )));
+        }
+        if row_len != 0 {
+            return Err(AnpassError::RowWidth {
+                line: last_exp_line,
+                expected: nunk,
+                found: row_len,
+            });
+        }
+        let exponents = if !is_sparse {
+            if exponents.is_empty() {
+                // no UNKNOWNS section, or no terms in it
+                na::DMatrix::zeros(ndisp_fields, 0)
+            } else {
+                let nrows = exponents.len() / nunk;
+                if nrows != ndisp_fields {
+                    return Err(AnpassError::Other(format!(
+                        "{nrows} rows of exponents given for {ndisp_fields} \
+                         variables"
+                    )));
+                }
+                na::DMatrix::from_row_slice(nrows, nunk, &exponents)
+            }
+        } else {
+            if sparse.len() != nunk {
+                return Err(AnpassError::Other(format!(
+                    "{} sparse unknowns given, but UNKNOWNS declares {nunk}",
+                    sparse.len()
+                )));
+            }
+            let mut exponents = na::DMatrix::zeros(ndisp_fields, sparse.len());
+            for (k, col) in sparse.into_iter().enumerate() {
+                for (v, p) in col {
+                    exponents[(v, k)] = p;
+                }
+            }
+            exponents
+        };
+        Ok(Self {
+            disps: Dmat::from_row_slice(ndisps, ndisp_fields, &disps),
+            energies: Dvec::from(energies),
+            exponents,
+            bias,
+            weights: if weights.is_empty() {
+                Option::None
+            } else {
+                Some(Dvec::from(weights))
+            },
+            surfaces: if surfaces.is_empty() {
+                Option::None
+            } else {
+                Some(Dmat::from_row_slice(
+                    surfaces.len() / nenergy_fields,
+                    nenergy_fields,
+                    &surfaces,
+                ))
+            },
+            header,
+        })
+    }
+
+    /// read comma-separated displacements from `r`, one point per line. If
+    /// `has_energy` is true, the last column of each row is the energy.
+    /// Otherwise, every column is a displacement and the energies are left
+    /// empty. Blank lines are ignored. The exponents are empty, with one row
+    /// per variable and no columns, and should be set by the caller. returns
+    /// an error if a field cannot be parsed or if the rows have different
+    /// numbers of fields
+    pub fn from_csv(
+        r: impl BufRead,
+        has_energy: bool,
+    ) -> Result<Self, AnpassError> {
+        let mut disps = Vec::new();
+        let mut energies = Vec::new();
+        let mut width = 0;
+        let mut npoints = 0;
+        for (lineno, line) in r.lines().enumerate() {
+            let line = line.map_err(|e| AnpassError::Other(format!("{e}")))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let f = line
+                .split(',')
+                .map(|s| {
+                    let s = s.trim();
+                    s.parse::<f64>().map_err(|_| AnpassError::InvalidField {
+                        line: lineno + 1,
+                        field: s.to_owned(),
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            if npoints == 0 {
+                width = f.len();
+            } else if f.len() != width {
+                return Err(AnpassError::RowWidth {
+                    line: lineno + 1,
+                    expected: width,
+                    found: f.len(),
+                });
+            }
+            if has_energy {
+                let (e, d) = f.split_last().unwrap();
+                disps.extend_from_slice(d);
+                energies.push(*e);
+            } else {
+                disps.extend(f);
+            }
+            npoints += 1;
+        }
+        let nvbl = if has_energy {
+            width.saturating_sub(1)
+        } else {
+            width
+        };
+        Ok(Self {
+            disps: Dmat::from_row_slice(npoints, nvbl, &disps),
+            energies: Dvec::from(energies),
+            exponents: na::DMatrix::zeros(nvbl, 0),
+            bias: None,
+            weights: None,
+            surfaces: None,
+            header: String::new(),
+        })
+    }
+
+    /// read one energy per line from `r`, in the same order as the rows of
+    /// `self.disps`, and replace `self.energies` with them. This is intended
+    /// for templates loaded without energies. Blank lines are ignored, and
+    /// Fortran `D` exponents are accepted. The first column of
+    /// `self.surfaces`, if present, is replaced as well. returns an error if a
+    /// line cannot be parsed or if the number of energies does not match the
+    /// number of displacements, leaving `self` unchanged in either case
+    pub fn set_energies_from(
+        &mut self,
+        r: impl BufRead,
+    ) -> Result<(), AnpassError> {
+        let mut energies = Vec::with_capacity(self.disps.nrows());
+        for (lineno, line) in r.lines().enumerate() {
+            let line = line.map_err(|e| AnpassError::Other(format!("{e}")))?;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            energies.push(parse_fortran_f64(line).map_err(|_| {
+                AnpassError::InvalidField {
+                    line: lineno + 1,
+                    field: line.to_owned(),
+                }
+            })?);
+        }
+        if energies.len() != self.disps.nrows() {
+            return Err(AnpassError::Other(format!(
+                "read {} energies for {} displacements",
+                energies.len(),
+                self.disps.nrows()
+            )));
+        }
+        self.energies = Dvec::from(energies);
+        if let Some(surfaces) = &mut self.surfaces {
+            surfaces.set_column(0, &self.energies);
+        }
+        Ok(())
+    }
+
+    /// fit every energy column in `self.surfaces`, or just `self.energies` if
+    /// there is only one, against the same design matrix. returns the
+    /// coefficients as a matrix with one column per surface, along with the
+    /// shared, unweighted design matrix. Each column matches the coefficients
+    /// `fit` would produce for that surface alone
+    pub fn fit_multi(&self) -> (Dmat, Dmat) {
+        let x = self.build_design_matrix();
+        let mut y = match &self.surfaces {
+            Some(s) => s.clone(),
+            None => Dmat::from_column_slice(
+                self.energies.len(),
+                1,
+                self.energies.as_slice(),
+            ),
+        };
+        let mut xw = x.clone();
+        if let Some(weights) = &self.weights {
+            for (i, w) in weights.iter().enumerate() {
+                let w = w.sqrt();
+                xw.row_mut(i).scale_mut(w);
+                y.row_mut(i).scale_mut(w);
+            }
+        }
+        let xt = xw.transpose();
+        let xtx = &xt * &xw;
+        let xty = xt * y;
+        let coeffs = match Cholesky::new(xtx.clone()) {
+            Some(chol) => chol.solve(&xty),
+            None => na::LU::new(xtx)
+                .solve(&xty)
+                .expect("LU decomposition also failed"),
+        };
+        (coeffs, x)
+    }
+
+    /// shift the displacements by `x0`, leaving the energies unchanged, and
+    /// fit in the shifted coordinates, so that the returned coefficients
+    /// describe the Taylor expansion about `x0`. This is useful when the
+    /// location of the minimum is already known and the Newton search in `run`
+    /// is unnecessary
+    pub fn fit_about(&self, x0: &Dvec) -> Result<(Dvec, Dmat), AnpassError> {
+        if x0.len() != self.disps.ncols() {
+            return Err(AnpassError::Other(format!(
+                "expansion point has {} coordinates, expected {}",
+                x0.len(),
+                self.disps.ncols()
+            )));
+        }
+        let bias = Bias {
+            disp: x0.clone(),
+            energy: 0.0,
+        };
+        Ok(self.bias(&bias).fit())
+    }
+
+    /// fit only the data points whose displacement norm is less than
+    /// `radius`, focusing the fit on the region near the reference geometry.
+    /// returns an error if fewer points than unknowns remain
+    pub fn fit_within_radius(
+        &self,
+        radius: f64,
+    ) -> Result<(Dvec, Dmat), AnpassError> {
+        let keep: Vec<_> = self
+            .disps
+            .row_iter()
+            .enumerate()
+            .filter(|(_, row)| row.norm() < radius)
+            .map(|(i, _)| i)
+            .collect();
+        let nunk = self.exponents.ncols();
+        if keep.len() < nunk {
+            return Err(AnpassError::Other(format!(
+                "only {} points within radius {radius}, need at least {nunk}",
+                keep.len()
+            )));
+        }
+        Ok(self.select_points(&keep).fit())
+    }
+
+    /// estimate the number of bytes needed by `fit` for the design matrix X
+    /// and the normal-equations matrix XᵀX, the dominant allocations for large
+    /// surfaces
+    pub fn estimated_fit_memory(&self) -> usize {
+        let npoints = self.disps.nrows();
+        let nunk = self.exponents.ncols();
+        let size = std::mem::size_of::<f64>();
+        npoints * nunk * size + nunk * nunk * size
+    }
+
+    /// return the condition number of the normal-equations matrix XᵀX used by
+    /// `fit`, the ratio of its largest to smallest eigenvalue. Large values
+    /// indicate that `fit_qr`, `fit_ridge`, or `fit_svd` may be more
+    /// appropriate. returns infinity if XᵀX is singular
+    pub fn condition_number(&self) -> f64 {
+        let x = self.build_design_matrix();
+        let evals = (x.transpose() * &x).symmetric_eigenvalues();
+        let (max, min) = (evals.max(), evals.min());
+        if min <= 0.0 {
+            f64::INFINITY
+        } else {
+            max / min
+        }
+    }
+
+    /// solve the ridge-regularized least-squares problem (XᵀX + λI)β = Xᵀy
+    /// with the smallest λ that brings the condition number of XᵀX + λI down
+    /// to `target_condition`. Since adding λI shifts every eigenvalue by λ,
+    /// this λ follows directly from the largest and smallest eigenvalues of
+    /// XᵀX, and it is zero if XᵀX is already well enough conditioned. returns
+    /// the coefficients, the design matrix, and the chosen λ. This ignores
+    /// `self.weights`. panics if `target_condition` is not greater than one,
+    /// since no λ can bring the condition number down to one or below
+    pub fn fit_auto_ridge(&self, target_condition: f64) -> (Dvec, Dmat, f64) {
+        assert!(
+            target_condition > 1.0,
+            "target condition number must be greater than one"
+        );
+        let x = self.build_design_matrix();
+        let xtx = x.transpose() * &x;
+        let evals = xtx.symmetric_eigenvalues();
+        let (max, min) = (evals.max(), evals.min());
+        let lambda = ((max - target_condition * min)
+            / (target_condition - 1.0))
+            .max(0.0);
+        let (coeffs, x) = self.fit_ridge(lambda);
+        (coeffs, x, lambda)
+    }
+
+    /// compute the minimum-norm least-squares solution using the singular value
+    /// decomposition of the design matrix, treating singular values smaller
+    /// than `rtol` times the largest as zero. Unlike `fit`, this tolerates
+    /// rank-deficient design matrices, such as those arising from redundant
+    /// polynomial terms, at the cost of a more expensive decomposition. Like
+    /// `fit_ridge`, this ignores `self.weights`
+    pub fn fit_svd(&self, rtol: f64) -> (Dvec, Dmat) {
+        let x = self.build_design_matrix();
+        (solve_svd(&x, &self.energies, rtol), x)
+    }
+
+    /// solve the least-squares problem directly from the QR decomposition of
+    /// the design matrix, X = QR, as β = R⁻¹Qᵀy. This avoids forming XᵀX,
+    /// which squares the condition number of X, so it is more accurate than
+    /// `fit` for ill-conditioned problems, such as high-degree polynomials.
+    /// The return value matches `fit`. Like `fit_ridge`, this ignores
+    /// `self.weights`. panics if R is singular
+    pub fn fit_qr(&self) -> (Dvec, Dmat) {
+        let x = self.build_design_matrix();
+        let qr = x.clone().qr();
+        let qty = qr.q().transpose() * &self.energies;
+        let coeffs = qr
+            .r()
+            .solve_upper_triangular(&qty)
+            .expect("R is singular in fit_qr");
+        (coeffs, x)
+    }
+
+    /// solve the ridge-regularized, or Tikhonov, least-squares problem β =
+    /// (XᵀX + λI)⁻¹Xᵀy with λ = `lambda`, which stabilizes the solution when
+    /// XᵀX is nearly singular at the cost of shrinking the coefficients. Like
+    /// `fit_auto_ridge`, this ignores `self.weights`, so `lambda = 0.0`
+    /// reproduces `fit` only when `self.weights` is `None`. panics if `lambda`
+    /// is negative or NaN
+    pub fn fit_ridge(&self, lambda: f64) -> (Dvec, Dmat) {
+        assert!(lambda >= 0.0, "ridge parameter must not be negative");
+        let x = self.build_design_matrix();
+        let xt = x.transpose();
+        let mut xtx = &xt * &x;
+        for i in 0..xtx.nrows() {
+            xtx[(i, i)] += lambda;
+        }
+        solve_least_squares(xtx, xt, &self.energies, x)
+    }
+
+    /// return the sensitivity of the fitted coefficients to the energy of the
+    /// data point at index `point`. since the least-squares solution β =
+    /// (XᵀWX)⁻¹XᵀWy solved by `fit` is linear in y, this is just the `point`
+    /// column of (XᵀWX)⁻¹XᵀW, where W is the diagonal matrix of
+    /// `self.weights`, or the identity if there are no weights
+    pub fn coeff_sensitivity(&self, point: usize) -> Dvec {
+        let x = self.build_design_matrix();
+        let xtw = self.weighted_transpose(&x);
+        let inv = invert(&(&xtw * &x));
+        (inv * xtw).column(point).into_owned()
+    }
+
+    /// return XᵀW, the transpose of the design matrix `x` with each column
+    /// multiplied by the weight of its data point, or just Xᵀ if there are no
+    /// weights
+    fn weighted_transpose(&self, x: &Dmat) -> Dmat {
+        let mut xt = x.transpose();
+        if let Some(weights) = &self.weights {
+            for (mut col, w) in xt.column_iter_mut().zip(weights.iter()) {
+                col *= *w;
+            }
+        }
+        xt
+    }
+
+    /// evaluate each of the monomials described by `self.exponents` at the
+    /// point `x`, giving the row of the design matrix corresponding to `x`
+    fn monomials(&self, x: &Dvec) -> Dvec {
+        let (nvbl, nunk) = self.exponents.shape();
+        Dvec::from_fn(nunk, |k, _| {
+            let mut prod = 1.0;
+            for j in 0..nvbl {
+                let ejk = self.exponents[(j, k)];
+                if ejk != 0 {
+                    prod *= x[j].powi(ejk);
+                }
+            }
+            prod
+        })
+    }
+
+    /// compute the `confidence` (e.g. 0.95) prediction interval for a new
+    /// observation at `x_new`, given the fitted `coeffs`, their covariance
+    /// matrix `cov`, and the residual variance `sigma2`. The interval accounts
+    /// for both the uncertainty in the coefficients and the noise in a new
+    /// observation, using the quantile of the t-distribution with n - p
+    /// degrees of freedom. For a weighted fit, `sigma2` is the variance of an
+    /// observation with unit weight, as used by `coefficient_covariance`.
+    /// returns the lower and upper bounds, or NaN for both if there are no
+    /// more data points than unknowns, leaving no degrees of freedom
+    pub fn prediction_interval(
+        &self,
+        x_new: &Dvec,
+        coeffs: &Dvec,
+        cov: &Dmat,
+        sigma2: f64,
+        confidence: f64,
+    ) -> (f64, f64) {
+        let Some(dof) = self
+            .disps
+            .nrows()
+            .checked_sub(self.exponents.ncols())
+            .filter(|&d| d > 0)
+        else {
+            return (f64::NAN, f64::NAN);
+        };
+        let phi = self.monomials(x_new);
+        let pred = self.eval(x_new, coeffs);
+        let se = (sigma2 + (phi.transpose() * cov * &phi)[0]).sqrt();
+        let t = stats::t_quantile(0.5 * (1.0 + confidence), dof as f64);
+        (pred - t * se, pred + t * se)
+    }
+
+    /// update the inverse of XᵀX, `inv_xtx`, and the fitted `coeffs` to
+    /// account for one additional data point at `new_disp` with energy
+    /// `new_energy`. The inverse is updated with the [Sherman-Morrison
+    /// formula](https://en.wikipedia.org/wiki/Sherman%E2%80%93Morrison_formula)
+    /// in O(unknowns²) operations instead of refactoring XᵀX. returns the
+    /// updated inverse and coefficients
+    pub fn update_fit_add_point(
+        &self,
+        inv_xtx: &Dmat,
+        coeffs: &Dvec,
+        new_disp: &[f64],
+        new_energy: f64,
+    ) -> (Dmat, Dvec) {
+        let phi = self.monomials(&Dvec::from_column_slice(new_disp));
+        let u = inv_xtx * &phi;
+        let denom = 1.0 + phi.dot(&u);
+        let inv = inv_xtx - (&u * u.transpose()) / denom;
+        let resid = new_energy - phi.dot(coeffs);
+        let coeffs = coeffs + &inv * phi * resid;
+        (inv, coeffs)
+    }
+
+    /// estimate the covariance matrix of the fitted `coeffs`, σ²(XᵀWX)⁻¹,
+    /// where W is the diagonal matrix of `self.weights`, or the identity if
+    /// there are no weights, and σ² is the weighted residual sum of squares
+    /// divided by the degrees of freedom, n - p. σ² is then the variance of an
+    /// energy with unit weight, matching the weighted least squares of `fit`.
+    /// This is the starting point for propagating the uncertainty in the fit
+    /// into the force constants. returns a matrix of NaN if there are no more
+    /// data points than unknowns, leaving no degrees of freedom
+    pub fn coefficient_covariance(&self, coeffs: &Dvec) -> Dmat {
+        self.covariance(coeffs, &self.build_design_matrix())
+    }
+
+    /// estimate the standard errors of the fitted `coeffs` from the square
+    /// roots of the diagonal of `coefficient_covariance`
+    pub fn coefficient_std_errors(&self, coeffs: &Dvec) -> Dvec {
+        self.coeff_std_errors(coeffs, &self.build_design_matrix())
+    }
+
+    /// `coefficient_covariance` with a precomputed design matrix `x`
+    fn covariance(&self, coeffs: &Dvec, x: &Dmat) -> Dmat {
+        let (n, p) = x.shape();
+        if n <= p {
+            return Dmat::from_element(p, p, f64::NAN);
+        }
+        let sigma2 =
+            self.weighted_sum_squared_residuals(coeffs, x) / (n - p) as f64;
+        sigma2 * invert(&(self.weighted_transpose(x) * x))
+    }
+
+    /// `sum_squared_residuals` with each squared residual multiplied by the
+    /// weight of its data point, if there are weights
+    fn weighted_sum_squared_residuals(&self, coeffs: &Dvec, x: &Dmat) -> f64 {
+        match &self.weights {
+            Some(weights) => {
+                let resid = &self.energies - x * coeffs;
+                resid.component_mul(&resid).dot(weights)
             }
+            None => self.sum_squared_residuals(coeffs, x),
         }
-        hess
+    }
+
+    /// `coefficient_std_errors` with a precomputed design matrix `x`
+    fn coeff_std_errors(&self, coeffs: &Dvec, x: &Dmat) -> Dvec {
+        self.covariance(coeffs, x).diagonal().map(f64::sqrt)
+    }
+
+    /// return the indices of the unknowns whose standard error exceeds
+    /// `ratio` times the magnitude of their coefficient, or equivalently whose
+    /// t-statistic is below 1/`ratio`. These terms are poorly determined by
+    /// the data and are candidates for removal from the model
+    pub fn ill_determined_terms(
+        &self,
+        coeffs: &Dvec,
+        x: &Dmat,
+        ratio: f64,
+    ) -> Vec<usize> {
+        let errs = self.coeff_std_errors(coeffs, x);
+        coeffs
+            .iter()
+            .zip(errs.iter())
+            .enumerate()
+            .filter(|(_, (c, e))| *e / c.abs() > ratio)
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// compute both the gradient and the Hessian of the function described by
+    /// `coeffs` at `x` in a single pass over the monomials, sharing the powers
+    /// of `x` between them. The results are identical to those of `grad` and
+    /// `hess` because the products are formed in the same order
+    pub fn grad_hess(&self, x: &Dvec, coeffs: &Dvec) -> (Dvec, Dmat) {
+        let (nvbl, nunk) = self.exponents.shape();
+        let mut grad = Dvec::zeros(nvbl);
+        let mut hess = Dmat::zeros(nvbl, nvbl);
+        // the variables appearing in the current monomial, along with their
+        // exponents and x raised to that exponent
+        let mut vars = Vec::with_capacity(nvbl);
+        for j in 0..nunk {
+            vars.clear();
+            for k in 0..nvbl {
+                let ekj = self.exponents[(k, j)];
+                if ekj != 0 {
+                    vars.push((k, ekj, x[k].powi(ekj)));
+                }
+            }
+            // product of the powers of every variable except those in `skip`
+            let rest = |mut coj: f64, skip: &[usize]| {
+                for &(k, _, pow) in &vars {
+                    if !skip.contains(&k) {
+                        coj *= pow;
+                    }
+                }
+                coj
+            };
+            // variables not in the monomial contribute nothing to either
+            // derivative
+            for (a, &(i, eij, _)) in vars.iter().enumerate() {
+                let fij = eij as f64;
+                let mut coj = coeffs[j] * fij;
+                if coj.abs() >= THR {
+                    if eij != 1 {
+                        coj *= x[i].powi(eij - 1);
+                    }
+                    grad[i] += rest(coj, &[i]);
+                }
+                for &(l, elj, _) in &vars[..a] {
+                    let flj = elj as f64;
+                    let mut coj = coeffs[j];
+                    coj *= fij * flj;
+                    if coj.abs() < THR {
+                        continue;
+                    }
+                    if eij != 1 {
+                        coj *= x[i].powi(eij - 1);
+                    }
+                    if elj != 1 {
+                        coj *= x[l].powi(elj - 1);
+                    }
+                    let coj = rest(coj, &[i, l]);
+                    hess[(i, l)] += coj;
+                    hess[(l, i)] += coj;
+                }
+                let mut coj = coeffs[j];
+                coj *= fij * (fij - 1.);
+                if coj.abs() >= THR {
+                    if eij != 2 {
+                        coj *= x[i].powi(eij - 2);
+                    }
+                    hess[(i, i)] += rest(coj, &[i]);
+                }
+            }
+        }
+        (grad, hess)
+    }
+
+    /// compute the third derivatives of the function described by `coeffs` at
+    /// `x`. The result is the full nvbl × nvbl × nvbl tensor flattened so that
+    /// the derivative with respect to variables i, j, and k is at index (i *
+    /// nvbl + j) * nvbl + k
+    pub fn third_derivatives(&self, x: &Dvec, coeffs: &Dvec) -> Vec<f64> {
+        let (nvbl, nunk) = self.exponents.shape();
+        let mut ret = vec![0.0; nvbl * nvbl * nvbl];
+        let mut counts = vec![0; nvbl];
+        for i in 0..nvbl {
+            for l in 0..=i {
+                for m in 0..=l {
+                    // number of times each variable is differentiated
+                    counts.fill(0);
+                    counts[i] += 1;
+                    counts[l] += 1;
+                    counts[m] += 1;
+                    let mut sum = 0.0;
+                    for j in 0..nunk {
+                        let mut coj = coeffs[j];
+                        for (k, &c) in counts.iter().enumerate() {
+                            let ekj = self.exponents[(k, j)];
+                            for p in 0..c {
+                                coj *= (ekj - p) as f64;
+                            }
+                        }
+                        if coj.abs() < THR {
+                            continue;
+                        }
+                        for (k, &c) in counts.iter().enumerate() {
+                            let ekj = self.exponents[(k, j)];
+                            if ekj != c {
+                                coj *= x[k].powi(ekj - c);
+                            }
+                        }
+                        sum += coj;
+                    }
+                    for (a, b, c) in [
+                        (i, l, m),
+                        (i, m, l),
+                        (l, i, m),
+                        (l, m, i),
+                        (m, i, l),
+                        (m, l, i),
+                    ] {
+                        ret[(a * nvbl + b) * nvbl + c] = sum;
+                    }
+                }
+            }
+        }
+        ret
+    }
+
+    /// verify that the analytic Hessian at `x` matches a central finite
+    /// difference of the analytic gradient with step size `h`, returning an
+    /// error if the maximum deviation exceeds `tol`
+    pub fn verify_hessian(
+        &self,
+        x: &Dvec,
+        coeffs: &Dvec,
+        h: f64,
+        tol: f64,
+    ) -> Result<(), AnpassError> {
+        let hess = self.hess(x, coeffs);
+        self.compare_hessian(x, coeffs, &hess, h, tol)
+    }
+
+    /// compare `hess` to a finite-difference Hessian at `x`. split out of
+    /// `verify_hessian` so that the comparison itself can be tested
+    fn compare_hessian(
+        &self,
+        x: &Dvec,
+        coeffs: &Dvec,
+        hess: &Dmat,
+        h: f64,
+        tol: f64,
+    ) -> Result<(), AnpassError> {
+        let max = self.hessian_deviation(x, coeffs, hess, h);
+        if max > tol {
+            return Err(AnpassError::Other(format!(
+                "Hessian deviates from finite difference by {max:.3e}"
+            )));
+        }
+        Ok(())
+    }
+
+    /// return the maximum absolute difference between `hess` and a central
+    /// finite difference of the analytic gradient at `x` with step size `h`
+    fn hessian_deviation(
+        &self,
+        x: &Dvec,
+        coeffs: &Dvec,
+        hess: &Dmat,
+        h: f64,
+    ) -> f64 {
+        let nvbl = x.len();
+        let mut max = 0.0_f64;
+        for j in 0..nvbl {
+            let mut xp = x.clone();
+            xp[j] += h;
+            let mut xm = x.clone();
+            xm[j] -= h;
+            let col =
+                (self.grad(&xp, coeffs) - self.grad(&xm, coeffs)) / (2.0 * h);
+            for i in 0..nvbl {
+                max = max.max((hess[(i, j)] - col[i]).abs());
+            }
+        }
+        max
+    }
+
+    /// compare the analytic gradient at `x` to a central finite difference of
+    /// `eval` with step size `h`, returning the maximum absolute discrepancy
+    pub fn check_gradient(&self, x: &Dvec, coeffs: &Dvec, h: f64) -> f64 {
+        let grad = self.grad(x, coeffs);
+        let mut max = 0.0_f64;
+        for i in 0..x.len() {
+            let mut xp = x.clone();
+            xp[i] += h;
+            let mut xm = x.clone();
+            xm[i] -= h;
+            let fd =
+                (self.eval(&xp, coeffs) - self.eval(&xm, coeffs)) / (2.0 * h);
+            max = max.max((grad[i] - fd).abs());
+        }
+        max
+    }
+
+    /// compare the analytic Hessian at `x` to a central finite difference of
+    /// the analytic gradient with step size `h`, returning the maximum
+    /// absolute discrepancy. See `verify_hessian` for a version that checks
+    /// the result against a tolerance
+    pub fn check_hessian(&self, x: &Dvec, coeffs: &Dvec, h: f64) -> f64 {
+        self.hessian_deviation(x, coeffs, &self.hess(x, coeffs), h)
+    }
+
+    /// follow the negative gradient of the function described by `coeffs`
+    /// from `start`, taking steps of `step` times the gradient until the
+    /// gradient norm falls below the zero threshold or `max_steps` steps have
+    /// been taken. returns every point visited, including `start`. Small steps
+    /// trace the true gradient flow, making this useful for visualizing the
+    /// path to the nearest minimum rather than for locating it efficiently
+    pub fn descent_path(
+        &self,
+        coeffs: &Dvec,
+        start: &Dvec,
+        step: f64,
+        max_steps: usize,
+    ) -> Vec<Dvec> {
+        let mut x = start.clone();
+        let mut path = vec![x.clone()];
+        for _ in 0..max_steps {
+            let grad = self.grad(&x, coeffs);
+            if grad.norm() < THR {
+                break;
+            }
+            x -= step * grad;
+            path.push(x.clone());
+        }
+        path
+    }
+
+    /// return the largest asymmetry, max |Hᵢⱼ - Hⱼᵢ|, of the Hessian at `x`.
+    /// The analytic Hessian is symmetric by construction, so any nonzero value
+    /// indicates a bug in `hess`
+    pub fn hessian_asymmetry(&self, x: &Dvec, coeffs: &Dvec) -> f64 {
+        let hess = self.hess(x, coeffs);
+        (&hess - hess.transpose()).amax()
+    }
+
+    /// return the indices (i, j), with i < j, and value of the off-diagonal
+    /// element of `hess` with the largest magnitude, identifying the most
+    /// strongly coupled pair of coordinates. returns NaN for the value if
+    /// `hess` has no off-diagonal elements
+    pub fn strongest_coupling(&self, hess: &Dmat) -> (usize, usize, f64) {
+        let mut ret = (0, 0, f64::NAN);
+        for i in 0..hess.nrows() {
+            for j in i + 1..hess.ncols() {
+                let v = hess[(i, j)];
+                if ret.2.is_nan() || v.abs() > ret.2.abs() {
+                    ret = (i, j, v);
+                }
+            }
+        }
+        ret
+    }
+
+    /// report whether the Hessian of the function described by `coeffs` at the
+    /// origin is singular, as judged by its smallest absolute eigenvalue
+    /// falling below `tol`. Newton's method will fail on its first step in
+    /// this case, so this is a cheap check before calling `newton`
+    pub fn origin_hessian_singular(&self, coeffs: &Dvec, tol: f64) -> bool {
+        let x = Dvec::zeros(self.n_variables());
+        let hess = self.hess(&x, coeffs);
+        hess.symmetric_eigenvalues().amin() < tol
     }
 
     /// characterize the stationary point described by `hess`
@@ -396,48 +1969,485 @@ impl Anpass {
         }
     }
 
-    /// use [Newton's optimization
-    /// method](https://en.wikipedia.org/wiki/Newton%27s_method_in_optimization)
-    /// to find the roots of the equation described by `coeffs` and
-    /// `self.exponents`. return the stationary point and the final Hessian
-    /// matrix
-    pub fn newton(
+    /// use [Newton's optimization
+    /// method](https://en.wikipedia.org/wiki/Newton%27s_method_in_optimization)
+    /// to find the roots of the equation described by `coeffs` and
+    /// `self.exponents`. return the stationary point and the final Hessian
+    /// matrix. See `try_newton` for a version with more detailed errors
+    pub fn newton(
+        &self,
+        coeffs: &Dvec,
+    ) -> Result<(Dvec, StatKind), AnpassError> {
+        Ok(self.try_newton(coeffs)?)
+    }
+
+    /// just like `newton`, but return a `NewtonError` describing the failure,
+    /// including the last iterate, if the iterations do not converge or the
+    /// Hessian cannot be inverted, rather than panicking in the latter case
+    pub fn try_newton(
+        &self,
+        coeffs: &Dvec,
+    ) -> Result<(Dvec, StatKind), NewtonError> {
+        self.newton_with(coeffs, NewtonOptions::default())
+    }
+
+    /// just like `try_newton`, but with the iteration limit, convergence
+    /// tolerance, and step size taken from `opts`
+    pub fn newton_with(
+        &self,
+        coeffs: &Dvec,
+        opts: NewtonOptions,
+    ) -> Result<(Dvec, StatKind), NewtonError> {
+        let (nvbl, _) = self.exponents.shape();
+        self.newton_impl(coeffs, Dvec::repeat(nvbl, 0.0), opts, None)
+    }
+
+    /// just like `newton_with`, but also return a `NewtonStep` for each
+    /// iteration, recording how the gradient norm and step size evolved, for
+    /// both converged and failed runs
+    pub fn newton_with_history(
+        &self,
+        coeffs: &Dvec,
+        opts: NewtonOptions,
+    ) -> (Result<(Dvec, StatKind), NewtonError>, Vec<NewtonStep>) {
+        let (nvbl, _) = self.exponents.shape();
+        let mut history = Vec::new();
+        let res = self.newton_impl(
+            coeffs,
+            Dvec::repeat(nvbl, 0.0),
+            opts,
+            Some(&mut history),
+        );
+        (res, history)
+    }
+
+    /// just like `try_newton`, but start the iterations from `x0` instead of
+    /// the origin, such as the stationary point of a previous, coarser fit.
+    /// panics if `x0` does not have one entry for each variable
+    pub fn newton_from(
+        &self,
+        coeffs: &Dvec,
+        x0: &Dvec,
+    ) -> Result<(Dvec, StatKind), NewtonError> {
+        assert_eq!(
+            x0.len(),
+            self.n_variables(),
+            "starting point does not match number of variables"
+        );
+        self.newton_impl(coeffs, x0.clone(), NewtonOptions::default(), None)
+    }
+
+    /// the shared implementation of the Newton methods, starting from `x`.
+    /// each iteration is pushed onto `history` if it is provided
+    fn newton_impl(
+        &self,
+        coeffs: &Dvec,
+        mut x: Dvec,
+        opts: NewtonOptions,
+        mut history: Option<&mut Vec<NewtonStep>>,
+    ) -> Result<(Dvec, StatKind), NewtonError> {
+        // the Levenberg-Marquardt shift, relaxed after every step so that the
+        // iterations approach pure Newton steps once the Hessian is positive
+        // definite, without jumping straight to the huge steps given by a
+        // nearly singular Hessian
+        let mut mu = 0.0;
+        for iteration in 0..opts.max_iter {
+            let (grad, hess) = self.grad_hess(&x, coeffs);
+            let inv = match opts.method {
+                StepMethod::Invert => try_invert(&hess),
+                StepMethod::LevenbergMarquardt => {
+                    shifted_inverse(&hess, &mut mu)
+                }
+                StepMethod::Eigen => eigen_inverse(&hess),
+            };
+            let Some(inv) = inv else {
+                return Err(NewtonError::SingularHessian { x });
+            };
+            let delta = opts.step_scale * inv * &grad;
+            let converged = delta.iter().all(|x| x.abs() <= opts.tol);
+            let step = if converged || !opts.line_search {
+                delta
+            } else {
+                self.backtrack(&x, &delta, grad.norm(), coeffs)
+            };
+            if let Some(history) = history.as_deref_mut() {
+                history.push(NewtonStep {
+                    iteration,
+                    x: x.clone(),
+                    grad_norm: grad.norm(),
+                    step_norm: step.norm(),
+                });
+            }
+            if converged {
+                return Ok((x, self.characterize(&hess)));
+            }
+            x -= step;
+            mu /= 10.0;
+        }
+        let grad_norm = self.grad(&x, coeffs).norm();
+        Err(NewtonError::NotConverged { x, grad_norm })
+    }
+
+    /// compute the harmonic estimate of the displacement from the origin to
+    /// the stationary point, -H⁻¹g, where g and H are the gradient and Hessian
+    /// at the origin. this is the undamped version of the first step taken by
+    /// `newton`. returns an error if H is not positive definite
+    pub fn harmonic_shift(&self, coeffs: &Dvec) -> Result<Dvec, AnpassError> {
+        let x = Dvec::zeros(self.n_variables());
+        let (grad, hess) = self.grad_hess(&x, coeffs);
+        let chol = Cholesky::new(hess).ok_or_else(|| {
+            AnpassError::Other(
+                "Hessian at the origin is not positive definite".to_string(),
+            )
+        })?;
+        Ok(-chol.solve(&grad))
+    }
+
+    /// halve `delta` until the norm of the gradient at `x - delta` is less
+    /// than `grad_norm`, the norm at `x`, and return the shortened step. If
+    /// no decrease is found after `MAX_HALVINGS`, the shortest step tried is
+    /// returned
+    fn backtrack(
+        &self,
+        x: &Dvec,
+        delta: &Dvec,
+        grad_norm: f64,
+        coeffs: &Dvec,
+    ) -> Dvec {
+        const MAX_HALVINGS: usize = 30;
+        let mut step = delta.clone();
+        for _ in 0..MAX_HALVINGS {
+            if self.grad(&(x - &step), coeffs).norm() < grad_norm {
+                break;
+            }
+            step *= 0.5;
+        }
+        step
+    }
+
+    /// assess the stability of the stationary point with a jackknife: refit
+    /// `n` times, each time leaving out a different data point chosen at
+    /// random according to `seed`, and run `newton` on each refitted surface.
+    /// `n` is capped at the number of data points, so at most every point is
+    /// left out once. returns the stationary points found, whose spread
+    /// indicates how well the data determine the location of the stationary
+    /// point
+    pub fn jackknife_stationary(
+        &self,
+        n: usize,
+        seed: u64,
+    ) -> Result<Vec<Dvec>, AnpassError> {
+        let npoints = self.disps.nrows();
+        let mut order: Vec<_> = (0..npoints).collect();
+        stats::Rng::new(seed).shuffle(&mut order);
+        order.truncate(n);
+        let mut ret = Vec::with_capacity(order.len());
+        for skip in order {
+            let keep: Vec<_> = (0..npoints).filter(|&i| i != skip).collect();
+            let anpass = self.select_points(&keep);
+            let (coeffs, _) = anpass.fit();
+            let (x, _) = anpass.newton(&coeffs)?;
+            ret.push(x);
+        }
+        Ok(ret)
+    }
+
+    /// evaluate the function described by `coeffs` at each of `points`,
+    /// typically a set of stationary points, and return the energies relative
+    /// to the lowest of them
+    pub fn relative_energies(
+        &self,
+        coeffs: &Dvec,
+        points: &[Dvec],
+    ) -> Vec<f64> {
+        let energies: Vec<_> =
+            points.iter().map(|p| self.eval(p, coeffs)).collect();
+        let min = energies.iter().cloned().fold(f64::INFINITY, f64::min);
+        energies.into_iter().map(|e| e - min).collect()
+    }
+
+    /// pair each of the `coeffs` with the exponents of its monomial, making
+    /// the correspondence between them explicit and independent of the column
+    /// order of `self.exponents`
+    pub fn labeled_coeffs(&self, coeffs: &Dvec) -> Vec<(Vec<i32>, f64)> {
+        self.exponents
+            .column_iter()
+            .zip(coeffs.iter())
+            .map(|(col, &c)| (col.iter().cloned().collect(), c))
+            .collect()
+    }
+
+    /// fit and return the `labeled_coeffs` sorted by the total degree of their
+    /// monomials and then lexicographically by exponents, along with the
+    /// matrix returned by `fit`
+    pub fn fit_ordered(&self) -> (Vec<(Vec<i32>, f64)>, Dmat) {
+        let (coeffs, x) = self.fit();
+        let mut labeled = self.labeled_coeffs(&coeffs);
+        labeled.sort_by(|(a, _), (b, _)| {
+            let da: i32 = a.iter().sum();
+            let db: i32 = b.iter().sum();
+            da.cmp(&db).then_with(|| a.cmp(b))
+        });
+        (labeled, x)
+    }
+
+    /// compute the harmonic vibrational frequencies in cm⁻¹ at the minimum of
+    /// the fitted surface, assuming displacements in Å, energies in hartrees,
+    /// and `masses` in amu, one for each variable. The Hessian at the
+    /// stationary point found by `newton` is mass-weighted by 1/√(mᵢmⱼ) and
+    /// diagonalized, and the frequencies are returned in ascending order, with
+    /// imaginary frequencies reported as negative values
+    pub fn frequencies(&self, masses: &Dvec) -> Result<Dvec, AnpassError> {
+        /// converts √(aJ / (Å² amu)) to cm⁻¹
+        const WAVENUMBER: f64 = 1302.7914;
+        let nvbl = self.n_variables();
+        if masses.len() != nvbl {
+            return Err(AnpassError::Other(format!(
+                "got {} masses for {nvbl} variables",
+                masses.len()
+            )));
+        }
+        let (coeffs, _) = self.fit();
+        let (x, _) = self.newton(&coeffs)?;
+        let mut hess = self.hess(&x, &coeffs);
+        for i in 0..nvbl {
+            for j in 0..nvbl {
+                hess[(i, j)] *= FAC / (masses[i] * masses[j]).sqrt();
+            }
+        }
+        let mut freqs: Vec<_> = hess
+            .symmetric_eigenvalues()
+            .iter()
+            .map(|&l| WAVENUMBER * l.signum() * l.abs().sqrt())
+            .collect();
+        freqs.sort_by(|a, b| a.total_cmp(b));
+        Ok(Dvec::from(freqs))
+    }
+
+    /// convert the displacements, energies, and exponents to a
+    /// `PlainAnpass`. The bias and weights are not included
+    pub fn to_plain(&self) -> PlainAnpass {
+        PlainAnpass {
+            disps: self
+                .disps
+                .row_iter()
+                .map(|r| r.iter().cloned().collect())
+                .collect(),
+            energies: self.energies.iter().cloned().collect(),
+            exponents: self
+                .exponents
+                .row_iter()
+                .map(|r| r.iter().cloned().collect())
+                .collect(),
+        }
+    }
+
+    /// construct an `Anpass` from a `PlainAnpass`, with no bias or weights.
+    /// panics if the rows of either matrix have different lengths
+    pub fn from_plain(plain: &PlainAnpass) -> Self {
+        fn to_matrix<T: na::Scalar + Copy>(rows: &[Vec<T>]) -> na::DMatrix<T> {
+            let ncols = rows.first().map_or(0, Vec::len);
+            assert!(
+                rows.iter().all(|r| r.len() == ncols),
+                "rows of PlainAnpass matrix have different lengths"
+            );
+            na::DMatrix::from_row_iterator(
+                rows.len(),
+                ncols,
+                rows.iter().flatten().cloned(),
+            )
+        }
+        Self {
+            disps: to_matrix(&plain.disps),
+            energies: Dvec::from(plain.energies.clone()),
+            exponents: to_matrix(&plain.exponents),
+            bias: None,
+            weights: None,
+            surfaces: None,
+            header: String::new(),
+        }
+    }
+
+    /// the lines preceding the format line in the input, or an empty string if
+    /// there were none
+    pub fn header(&self) -> &str {
+        &self.header
+    }
+
+    /// replace the header written by `Display` before the format line. Since
+    /// the header replaces the default preamble, it should contain the
+    /// `INDEPENDENT VARIABLES` and `DATA POINTS` sections expected by the
+    /// Fortran version. A trailing newline is added if missing
+    pub fn with_header(mut self, header: impl Into<String>) -> Self {
+        self.header = header.into();
+        if !self.header.is_empty() && !self.header.ends_with('\n') {
+            self.header.push('\n');
+        }
+        self
+    }
+
+    /// return the total degree of the polynomial, the highest total degree
+    /// among its monomials
+    pub fn total_degree(&self) -> i32 {
+        self.exponents
+            .column_iter()
+            .map(|col| col.sum())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// return a new `Anpass` with each column of the displacements multiplied
+    /// by the corresponding element of `scales`. This is a deliberate physical
+    /// reweighting of the coordinates, such as bringing bond-length and angle
+    /// displacements to a common scale. panics if the length of `scales` does
+    /// not match the number of variables
+    pub fn scale_coordinates(&self, scales: &Dvec) -> Self {
+        assert_eq!(
+            scales.len(),
+            self.disps.ncols(),
+            "number of scale factors does not match number of variables"
+        );
+        let mut disps = self.disps.clone();
+        for (mut col, s) in disps.column_iter_mut().zip(scales.iter()) {
+            col *= *s;
+        }
+        Self {
+            disps,
+            ..self.clone()
+        }
+    }
+
+    /// return a new `Anpass` with the exponent matrix transposed, swapping the
+    /// variable and unknown axes, for exchanging exponents with tools that use
+    /// the opposite orientation. This changes the meaning of the fit entirely,
+    /// and the result will generally not match the columns of `disps`, so it
+    /// is only useful for adapting imports and exports
+    pub fn transpose_exponents(&self) -> Self {
+        Self {
+            exponents: self.exponents.transpose(),
+            ..self.clone()
+        }
+    }
+
+    /// return a new `Anpass` containing only the data points at the indices
+    /// in `points`, in the order given
+    fn select_points(&self, points: &[usize]) -> Self {
+        Self {
+            disps: self.disps.select_rows(points),
+            energies: if self.energies.is_empty() {
+                self.energies.clone()
+            } else {
+                self.energies.select_rows(points)
+            },
+            weights: self.weights.as_ref().map(|w| w.select_rows(points)),
+            surfaces: self.surfaces.as_ref().map(|s| s.select_rows(points)),
+            ..self.clone()
+        }
+    }
+
+    /// randomly partition the data points into training and test sets, with
+    /// approximately `test_fraction` of the points in the test set. Both
+    /// halves share the exponents of `self`, and the partition is
+    /// deterministic for a given `seed`. returns the training and test sets
+    pub fn train_test_split(
         &self,
-        coeffs: &Dvec,
-    ) -> Result<(Dvec, StatKind), AnpassError> {
-        const MAXIT: usize = 100;
-        let (nvbl, _) = self.exponents.shape();
-        let mut x = Dvec::repeat(nvbl, 0.0);
-        for _ in 0..MAXIT {
-            let grad = self.grad(&x, coeffs);
-            let hess = self.hess(&x, coeffs);
-            let inv = invert(&hess);
-            let delta = 0.5 * inv * grad;
-            if delta.iter().all(|x| x.abs() <= 1.1e-8) {
-                return Ok((x, self.characterize(&hess)));
-            }
-            x -= delta;
+        test_fraction: f64,
+        seed: u64,
+    ) -> (Self, Self) {
+        let n = self.disps.nrows();
+        let ntest = ((n as f64 * test_fraction).round() as usize).min(n);
+        let mut idx: Vec<_> = (0..n).collect();
+        stats::Rng::new(seed).shuffle(&mut idx);
+        let (test, train) = idx.split_at_mut(ntest);
+        test.sort();
+        train.sort();
+        (self.select_points(train), self.select_points(test))
+    }
+
+    /// return summary statistics of `self.energies`, or `None` if there are no
+    /// energies
+    pub fn energy_stats(&self) -> Option<EnergyStats> {
+        let n = self.energies.len();
+        if n == 0 {
+            return None;
         }
-        Err(AnpassError("too many Newton iterations".to_string()))
+        let mut sorted: Vec<_> = self.energies.iter().cloned().collect();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        Some(EnergyStats {
+            min: sorted[0],
+            max: sorted[n - 1],
+            mean: self.energies.mean(),
+            // the average of the two middle elements for even n, or the
+            // middle element twice for odd n
+            median: 0.5 * (sorted[(n - 1) / 2] + sorted[n / 2]),
+            std_dev: self.energies.variance().sqrt(),
+        })
     }
 
-    /// evaluate the function at the point `x`
-    pub fn eval(&self, x: &Dvec, coeffs: &Dvec) -> f64 {
-        let mut sum = 0.0;
-        for (k, prod) in coeffs.iter().enumerate() {
-            let mut prod = *prod;
-            if prod.abs() < THR {
-                continue;
-            }
-            for (j, xi) in x.iter().enumerate() {
-                let ejk = self.exponents[(j, k)];
-                if ejk != 0 {
-                    prod *= xi.powi(ejk);
+    /// return the number of distinct displacement values, within `tol`, taken
+    /// by each coordinate. A coordinate sampled at only two levels cannot
+    /// support a quartic term in that variable, for example
+    pub fn sampling_levels(&self, tol: f64) -> Vec<usize> {
+        self.disps
+            .column_iter()
+            .map(|col| {
+                let mut vals: Vec<f64> = col.iter().cloned().collect();
+                vals.sort_by(|a, b| a.total_cmp(b));
+                let mut levels = 0;
+                let mut last = f64::NEG_INFINITY;
+                for v in vals {
+                    if v - last > tol {
+                        levels += 1;
+                        last = v;
+                    }
                 }
-            }
-            sum += prod;
-        }
-        sum
+                levels
+            })
+            .collect()
+    }
+
+    /// evaluate the function described by `coeffs` at `n` evenly-spaced points
+    /// along the line from `a` to `b`. returns the scan parameters t in [0, 1]
+    /// and the energies at the corresponding points a + t(b - a)
+    pub fn line_scan(
+        &self,
+        coeffs: &Dvec,
+        a: &Dvec,
+        b: &Dvec,
+        n: usize,
+    ) -> (Dvec, Dvec) {
+        let nvbl = self.n_variables();
+        assert_eq!(
+            a.len(),
+            nvbl,
+            "start point has the wrong number of variables"
+        );
+        assert_eq!(
+            b.len(),
+            nvbl,
+            "end point has the wrong number of variables"
+        );
+        let step = if n > 1 { 1.0 / (n - 1) as f64 } else { 0.0 };
+        let ts = Dvec::from_fn(n, |i, _| i as f64 * step);
+        let dir = b - a;
+        let energies = ts.map(|t| self.eval(&(a + t * &dir), coeffs));
+        (ts, energies)
+    }
+
+    /// return a `Bias` recentering the data on its lowest-energy point, or
+    /// `None` if there are no energies. Applying this with `bias` is a fast
+    /// alternative to the Newton search in `run` when the sampled minimum is a
+    /// good enough reference
+    pub fn bias_to_min(&self) -> Option<Bias> {
+        let (imin, _) = self
+            .energies
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))?;
+        Some(Bias {
+            disp: self.disps.row(imin).transpose(),
+            energy: self.energies[imin],
+        })
     }
 
     pub fn bias(&self, bias: &Bias) -> Self {
@@ -453,48 +2463,309 @@ impl Anpass {
         Self {
             disps: Dmat::from_row_slice(rows, cols, &disps),
             energies: Dvec::from(energies),
+            surfaces: self
+                .surfaces
+                .as_ref()
+                .map(|s| s.add_scalar(-bias.energy)),
+            ..self.clone()
+        }
+    }
+
+    /// just like `bias`, but subtract `bias` from the displacements and
+    /// energies of `self` in place, without cloning any of its matrices
+    pub fn bias_mut(&mut self, bias: &Bias) {
+        for mut row in self.disps.row_iter_mut() {
+            row -= bias.disp.transpose();
+        }
+        self.energies.add_scalar_mut(-bias.energy);
+        if let Some(surfaces) = &mut self.surfaces {
+            surfaces.add_scalar_mut(-bias.energy);
+        }
+    }
+
+    /// undo `bias`, adding `bias.disp` back to each displacement and
+    /// `bias.energy` back to each energy to return to the original frame. up
+    /// to rounding, `self.bias(b).unbias(b)` reproduces `self`
+    pub fn unbias(&self, bias: &Bias) -> Self {
+        let mut disps = self.disps.clone();
+        for mut row in disps.row_iter_mut() {
+            row += bias.disp.transpose();
+        }
+        Self {
+            disps,
+            energies: self.energies.add_scalar(bias.energy),
+            surfaces: self.surfaces.as_ref().map(|s| s.add_scalar(bias.energy)),
             ..self.clone()
         }
     }
 
+    /// convert the fitted `coeffs` into force constants for fort.9903 by
+    /// scaling each by the factorials of its exponents. The indices of each
+    /// force constant are padded with zeros to the four columns of the
+    /// fort.9903 format, so only force constants above fourth order have more
+    /// than four indices. The force constants are sorted by their indices, so
+    /// the result does not depend on the order of the columns of
+    /// `exponents`, and any terms sharing the same indices are summed into a
+    /// single force constant. panics if any exponent is negative
     pub fn make9903(&self, coeffs: &Dvec) -> Vec<Fc> {
+        self.make9903_with(coeffs, FAC)
+    }
+
+    /// just like `make9903`, but leave out the force constants whose absolute
+    /// value is below `tol`, such as 1e-10, the threshold below which the
+    /// derivative methods treat a term as zero. `make9903` itself keeps every
+    /// term, however small
+    pub fn make9903_filtered(&self, coeffs: &Dvec, tol: f64) -> Vec<Fc> {
+        let mut ret = self.make9903(coeffs);
+        ret.retain(|fc| fc.value().abs() >= tol);
+        ret
+    }
+
+    /// just like `make9903`, but multiply the force constants by `fac` in
+    /// place of the default conversion factor, 4.359813653. pass 1.0 to keep
+    /// them in the units of the energies and displacements
+    pub fn make9903_with(&self, coeffs: &Dvec, fac: f64) -> Vec<Fc> {
+        assert!(
+            self.exponents.iter().all(|&e| e >= 0),
+            "negative exponents cannot be converted to force constants"
+        );
         let (c, r) = self.exponents.shape();
         let mut ret = Vec::new();
         for i in 0..r {
             let mut ifact = 1.0;
-            let mut ictmp = [0; 4];
-            let mut iccount: usize = 0;
+            let mut ictmp = Vec::with_capacity(4);
             for j in (0..c).rev() {
                 let iexpo = self.exponents[(j, i)];
-                ifact *= [1.0, 1.0, 2.0, 6.0, 24.0][iexpo as usize];
-                if iexpo > 0 {
-                    for k in 0..iexpo {
-                        ictmp[iccount + k as usize] = j + 1;
-                    }
-                    iccount += iexpo as usize;
+                ifact *= factorial(iexpo);
+                for _ in 0..iexpo {
+                    ictmp.push(j + 1);
                 }
             }
-            let ffcc = coeffs[i] * ifact * FAC;
-            let [a, b, c, d] = ictmp;
-            ret.push(Fc(a, b, c, d, ffcc));
+            if ictmp.len() < 4 {
+                ictmp.resize(4, 0);
+            }
+            let ffcc = coeffs[i] * ifact * fac;
+            ret.push(Fc::from_indices(ictmp, ffcc));
         }
+        ret.sort_by(|a, b| a.indices().cmp(b.indices()));
+        // duplicate columns of `exponents` give the same derivative, so
+        // combine them into a single force constant
+        ret.dedup_by(|fc, kept| {
+            let same = fc.indices() == kept.indices();
+            if same {
+                kept.value += fc.value;
+            }
+            same
+        });
         ret
     }
 
+    /// write `self` to `w` in the input format read by `load`, like
+    /// `Display`, but with the displacements formatted as
+    /// F`disp_width`.`disp_prec` and the energies with `energy_prec` digits
+    /// after the decimal point. The format line is adjusted to match, and the
+    /// energy column is skipped entirely if `self.energies` is empty
+    pub fn write_input<W: Write>(
+        &self,
+        w: &mut W,
+        disp_width: usize,
+        disp_prec: usize,
+        energy_prec: usize,
+    ) {
+        let mut s = String::new();
+        self.fmt_input(&mut s, disp_width, disp_prec, energy_prec)
+            .unwrap();
+        w.write_all(s.as_bytes()).unwrap();
+    }
+
+    /// write `self` as an anpass input file to `f`, with the displacements
+    /// formatted as F`disp_width`.`disp_prec` and the energies and weights
+    /// with `energy_prec` digits after the decimal point and a width 8
+    /// greater. The energy column is omitted if `self.energies` is empty
+    fn fmt_input<F: std::fmt::Write>(
+        &self,
+        f: &mut F,
+        disp_width: usize,
+        disp_prec: usize,
+        energy_prec: usize,
+    ) -> std::fmt::Result {
+        let (rows, cols) = self.disps.shape();
+        if self.header.is_empty() {
+            writeln!(
+                f,
+                "!INPUT
+TITLE
+from rust-anpass by BRW
+INDEPENDENT VARIABLES"
+            )?;
+            writeln!(f, "{cols:4}")?;
+            writeln!(
+                f,
+                "DATA POINTS
+{:5}{:5}",
+                rows, -2
+            )?;
+        } else {
+            // the counts following these headings may be stale if the data
+            // points were selected or the header was replaced, so regenerate
+            // them from the current data
+            let mut count = None;
+            for line in self.header.lines() {
+                match count.take() {
+                    Some(n) => writeln!(f, "{}", replace_count(line, n))?,
+                    None => writeln!(f, "{line}")?,
+                }
+                match line.trim() {
+                    "INDEPENDENT VARIABLES" => count = Some(cols),
+                    "DATA POINTS" => count = Some(rows),
+                    _ => {}
+                }
+            }
+        }
+        let (dw, dp, ep) = (disp_width, disp_prec, energy_prec);
+        let ew = ep + 8;
+        match &self.surfaces {
+            Some(s) => {
+                writeln!(f, "({cols}F{dw}.{dp},{}f{ew}.{ep})", s.ncols())?
+            }
+            None => writeln!(f, "({cols}F{dw}.{dp},f{ew}.{ep})")?,
+        }
+        for row in 0..rows {
+            for col in 0..cols {
+                write!(f, "{:dw$.dp$}", self.disps[(row, col)])?;
+            }
+            match &self.surfaces {
+                Some(s) => {
+                    for e in s.row(row).iter() {
+                        write!(f, "{e:ew$.ep$}")?;
+                    }
+                }
+                None if self.energies.is_empty() => {}
+                None => write!(f, "{:ew$.ep$}", self.energies[row])?,
+            }
+            writeln!(f)?;
+        }
+        if let Some(weights) = &self.weights {
+            writeln!(f, "WEIGHTS")?;
+            for w in weights {
+                writeln!(f, "{w:ew$.ep$}")?;
+            }
+        }
+        writeln!(f, "UNKNOWNS")?;
+        let (rows, cols) = self.exponents.shape();
+        writeln!(f, "{cols:4}")?;
+        writeln!(f, "FUNCTION")?;
+        for row in 0..rows {
+            for col in 0..cols {
+                if col > 0 && col % 16 == 0 {
+                    writeln!(f)?;
+                }
+                write!(f, "{:5}", self.exponents[(row, col)])?;
+            }
+            writeln!(f)?;
+        }
+        writeln!(
+            f,
+            "END OF DATA
+!FIT
+!STATIONARY POINT
+!END"
+        )?;
+        Ok(())
+    }
+
     pub fn write9903<W: Write>(&self, w: &mut W, fcs: &[Fc]) {
+        self.write9903_precision(w, fcs, fc::DEFAULT_PRECISION);
+    }
+
+    /// just like `write9903`, but write the values of the force constants with
+    /// `precision` digits after the decimal point instead of the default 12,
+    /// in a field 8 wider than `precision`
+    pub fn write9903_precision<W: Write>(
+        &self,
+        w: &mut W,
+        fcs: &[Fc],
+        precision: usize,
+    ) {
         writeln!(w).unwrap();
         for fc in fcs {
-            writeln!(w, "{fc}",).unwrap();
+            writeln!(w, "{fc:.precision$}").unwrap();
         }
     }
 
+    /// just like `write9903`, but also compute and return a checksum of the
+    /// bytes written, as computed by `fc::checksum`. Recording this allows
+    /// later verification that the file was not truncated or corrupted
+    pub fn write9903_checksum<W: Write>(&self, w: &mut W, fcs: &[Fc]) -> u64 {
+        let mut w = fc::ChecksumWriter::new(w);
+        self.write9903(&mut w, fcs);
+        w.checksum()
+    }
+
     /// perform the initial fitting, find the stationary point, bias to the new
     /// stationary point, and refit. returns the force constants at the
     /// stationary point, the bias (long line), and the sum of squared residuals
     pub fn run(&self) -> Result<(Vec<Fc>, Bias, f64, StatKind), AnpassError> {
+        self.run_guarded(false)
+    }
+
+    /// norm of the gradient of the function described by `coeffs` at the
+    /// origin. a small value indicates that the displacements are already
+    /// centered on a stationary point
+    pub fn origin_gradient_norm(&self, coeffs: &Dvec) -> f64 {
+        self.grad(&Dvec::zeros(self.n_variables()), coeffs).norm()
+    }
+
+    /// just like `run`, but skip the bias and refit when the fitted surface is
+    /// already stationary at the origin, as determined by an
+    /// `origin_gradient_norm` below 1e-8, in units of energy per unit
+    /// displacement. In that case the force constants from the initial fit are
+    /// returned along with `None` for the bias
+    pub fn run_auto(&self) -> Result<(Vec<Fc>, Option<Bias>), AnpassError> {
+        const GRAD_THRESH: f64 = 1e-8;
+        let (coeffs, _) = self.fit();
+        if self.origin_gradient_norm(&coeffs) < GRAD_THRESH {
+            return Ok((self.make9903(&coeffs), None));
+        }
+        let (fcs, bias, _, _) = self.run()?;
+        Ok((fcs, Some(bias)))
+    }
+
+    /// just like `run`, but if `require_min` is true, return a
+    /// `WrongStationaryType` error instead of biasing to the stationary point
+    /// if it is not a minimum. Biasing to a maximum or saddle point is usually
+    /// a mistake, but some users are deliberately looking for saddle points
+    pub fn run_guarded(
+        &self,
+        require_min: bool,
+    ) -> Result<(Vec<Fc>, Bias, f64, StatKind), AnpassError> {
+        let report = self.run_report_guarded(require_min)?;
+        Ok((
+            self.make9903(&report.coeffs),
+            report.bias,
+            report.sum_squared_residuals,
+            report.kind,
+        ))
+    }
+
+    /// perform the same steps as `run`, but return a `FitReport` with the
+    /// refitted coefficients and residuals instead of the force constants,
+    /// which can be recovered with `make9903`
+    pub fn run_report(&self) -> Result<FitReport, AnpassError> {
+        self.run_report_guarded(false)
+    }
+
+    /// the shared implementation of `run_guarded` and `run_report`
+    fn run_report_guarded(
+        &self,
+        require_min: bool,
+    ) -> Result<FitReport, AnpassError> {
         let (coeffs, _) = self.fit();
         // find stationary point
         let (x, kind) = self.newton(&coeffs)?;
+        if require_min && kind != StatKind::Min {
+            return Err(AnpassError::WrongStationaryType { found: kind });
+        }
         // determine energy at stationary point
         let e = self.eval(&x, &coeffs);
         // bias the displacements and energies to the new stationary point
@@ -502,16 +2773,75 @@ impl Anpass {
         let anpass = self.bias(&bias);
         // perform the refitting
         let (coeffs, f) = anpass.fit();
-        Ok((
-            anpass.make9903(&coeffs),
-            bias,
-            anpass.residuals(&coeffs, &f),
+        Ok(FitReport {
             kind,
-        ))
+            sum_squared_residuals: anpass.sum_squared_residuals(&coeffs, &f),
+            computed: &f * &coeffs,
+            observed: anpass.energies,
+            bias,
+            coeffs,
+        })
+    }
+
+    /// return the residual of each data point, observed minus computed, `y -
+    /// Xβ`, using the same design matrix as `fit`. panics if `self.energies`
+    /// is empty
+    pub fn point_residuals(&self, coeffs: &Dvec) -> Dvec {
+        self.point_residuals_with_design(coeffs, None)
+    }
+
+    /// just like `point_residuals`, but use the precomputed design matrix `x`
+    /// if present
+    pub fn point_residuals_with_design(
+        &self,
+        coeffs: &Dvec,
+        x: Option<&Dmat>,
+    ) -> Dvec {
+        assert!(
+            !self.energies.is_empty(),
+            "cannot compute residuals without energies"
+        );
+        match x {
+            Some(x) => &self.energies - x * coeffs,
+            None => &self.energies - self.build_design_matrix() * coeffs,
+        }
+    }
+
+    /// compute the RMSD, maximum absolute residual, and R² of the fit
+    /// described by `coeffs` from its `point_residuals`. panics if
+    /// `self.energies` is empty
+    pub fn fit_statistics(&self, coeffs: &Dvec) -> FitStats {
+        self.fit_statistics_with_design(coeffs, None)
+    }
+
+    /// just like `fit_statistics`, but use the precomputed design matrix `x`
+    /// if present
+    pub fn fit_statistics_with_design(
+        &self,
+        coeffs: &Dvec,
+        x: Option<&Dmat>,
+    ) -> FitStats {
+        let resid = self.point_residuals_with_design(coeffs, x);
+        let n_points = resid.len();
+        let ss_res = resid.norm_squared();
+        let mean = self.energies.mean();
+        let ss_tot = self.energies.map(|e| (e - mean).powi(2)).sum();
+        FitStats {
+            rmsd: (ss_res / n_points as f64).sqrt(),
+            max_abs_residual: resid.amax(),
+            r_squared: 1.0 - ss_res / ss_tot,
+            n_points,
+        }
     }
 
     /// evaluate the function and return the sum of squared residuals
+    #[deprecated(note = "renamed to `sum_squared_residuals`")]
     pub fn residuals(&self, coeffs: &Dvec, f: &Dmat) -> f64 {
+        self.sum_squared_residuals(coeffs, f)
+    }
+
+    /// evaluate the function and return the sum of squared residuals
+    pub fn sum_squared_residuals(&self, coeffs: &Dvec, f: &Dmat) -> f64 {
         let prod = f * coeffs;
         let mut sum = 0.0;
         for (i, obsv) in self.energies.iter().enumerate() {
@@ -522,30 +2852,69 @@ impl Anpass {
         sum
     }
 
+    /// return the index and signed residual, observed minus computed as in
+    /// `point_residuals`, of the data point with the largest absolute residual
+    pub fn max_residual(&self, coeffs: &Dvec, f: &Dmat) -> (usize, f64) {
+        let resid = self.point_residuals_with_design(coeffs, Some(f));
+        let i = resid.iamax();
+        (i, resid[i])
+    }
+
+    /// compare the predictions of the functions described by `coeffs_a` and
+    /// `coeffs_b` at each of the data points, returning the largest absolute
+    /// difference between them and the index of the point where it occurs
+    pub fn compare_predictions(
+        &self,
+        coeffs_a: &Dvec,
+        coeffs_b: &Dvec,
+    ) -> (f64, usize) {
+        let x = self.build_design_matrix();
+        let diff = x * (coeffs_a - coeffs_b);
+        let i = diff.iamax();
+        (diff[i].abs(), i)
+    }
+
+    /// compute the overall F-statistic (SSᵣₑ₉/p) / (SSᵣₑₛ/(n - p - 1)) for the
+    /// fit described by `coeffs` and `f`, testing whether the model explains
+    /// significantly more of the variance than the mean energy alone. p is
+    /// the number of unknowns excluding the constant term. returns NaN if
+    /// the model has no constant term, a column of `self.exponents` that is
+    /// all zeros, or if there are too few points for the residual degrees of
+    /// freedom, n - p - 1, to be positive
+    pub fn f_statistic(&self, coeffs: &Dvec, f: &Dmat) -> f64 {
+        let (n, nunk) = f.shape();
+        let has_constant = self
+            .exponents
+            .column_iter()
+            .any(|col| col.iter().all(|&e| e == 0));
+        if nunk == 0 || !has_constant {
+            return f64::NAN;
+        }
+        let p = nunk - 1;
+        if n <= p + 1 {
+            return f64::NAN;
+        }
+        let pred = f * coeffs;
+        let mean = self.energies.mean();
+        let ss_reg = pred.map(|y| (y - mean).powi(2)).sum();
+        let ss_res = self.sum_squared_residuals(coeffs, f);
+        (ss_reg / p as f64) / (ss_res / (n - p - 1) as f64)
+    }
+
     /// evaluate the function residuals of a at the point x and print them
-    fn print_residuals<W>(&self, w: &mut W, coeffs: &Dvec, f: &Dmat) -> f64
+    fn print_residuals<W>(
+        &self,
+        w: &mut W,
+        coeffs: &Dvec,
+        f: &Dmat,
+    ) -> std::io::Result<f64>
     where
         W: std::io::Write,
     {
-        let prod = f * coeffs;
-        let mut sum = 0.0;
-        for (i, obsv) in self.energies.iter().enumerate() {
-            let comp = prod[i];
-            let resi = comp - obsv;
-            writeln!(
-                w,
-                "{:5}{:20.12}{:20.12}{:20.8e}",
-                i + 1,
-                comp,
-                obsv,
-                resi
-            )
-            .unwrap();
-            sum += resi * resi;
-        }
-        writeln!(w, "WEIGHTED SUM OF SQUARED RESIDUALS IS {sum:17.8e}")
-            .unwrap();
-        sum
+        let mut s = String::new();
+        let sum = fmt_residuals(&mut s, &(f * coeffs), &self.energies).unwrap();
+        w.write_all(s.as_bytes())?;
+        Ok(sum)
     }
 
     /// just like `run` but prints debugging output
@@ -562,9 +2931,12 @@ impl Anpass {
         // determine energy at stationary point
         let e = self.eval(&x, &coeffs);
 
-        writeln!(w, "WHERE ENERGY IS {e:20.12}").unwrap();
+        let werr = |e: std::io::Error| {
+            AnpassError::Other(format!("failed to write debug output with {e}"))
+        };
+        writeln!(w, "WHERE ENERGY IS {e:20.12}").map_err(werr)?;
         for c in &x {
-            writeln!(w, "{c:18.10}").unwrap();
+            writeln!(w, "{c:18.10}").map_err(werr)?;
         }
 
         // bias the displacements and energies to the new stationary point
@@ -575,9 +2947,82 @@ impl Anpass {
         Ok((
             anpass.make9903(&coeffs),
             bias,
-            self.print_residuals(w, &coeffs, &f),
+            self.print_residuals(w, &coeffs, &f).map_err(werr)?,
         ))
     }
+
+    /// perform the same steps as `run` and write a complete record of them to
+    /// the directory `dir`, which is created if it does not already exist. The
+    /// files written are `input.anpass`, an echo of the input from
+    /// `write_input`; `report.txt`, the `FitReport` from `run_report`;
+    /// `coeffs.txt`, the refitted coefficients; and `fort.9903`, the force
+    /// constants
+    pub fn dump_run(&self, dir: &str) -> Result<(), AnpassError> {
+        let dir = std::path::Path::new(dir);
+        std::fs::create_dir_all(dir).map_err(|e| {
+            AnpassError::Other(format!(
+                "failed to create {} with {e}",
+                dir.display()
+            ))
+        })?;
+        let write = |name: &str, contents: &[u8]| {
+            let path = dir.join(name);
+            std::fs::write(&path, contents).map_err(|e| {
+                AnpassError::Other(format!(
+                    "failed to write {} with {e}",
+                    path.display()
+                ))
+            })
+        };
+
+        let mut input = Vec::new();
+        self.write_input(&mut input, 12, 8, 12);
+        write("input.anpass", &input)?;
+
+        let report = self.run_report()?;
+        write("report.txt", report.to_string().as_bytes())?;
+
+        let coeffs: String = report
+            .coeffs
+            .iter()
+            .map(|c| format!("{c:20.12}\n"))
+            .collect();
+        write("coeffs.txt", coeffs.as_bytes())?;
+
+        let mut fort = Vec::new();
+        self.write9903(&mut fort, &self.make9903(&report.coeffs));
+        write("fort.9903", &fort)
+    }
+}
+
+/// A least-squares problem with fixed displacements and exponents, prepared
+/// once so that it can be solved repeatedly for different energies. Both the
+/// Cholesky factorization of XᵀX and Xᵀ itself are cached, making each call to
+/// `solve` O(unknowns²) instead of rebuilding and refactoring the design matrix
+pub struct PreparedFit {
+    chol: Cholesky<f64, na::Dyn>,
+    xt: Dmat,
+}
+
+impl PreparedFit {
+    /// prepare the fitting problem described by `anpass`, including its
+    /// weights if present. returns an error if XᵀX is not positive definite
+    pub fn new(anpass: &Anpass) -> Result<Self, AnpassError> {
+        let x = anpass.build_design_matrix();
+        let xt = anpass.weighted_transpose(&x);
+        let xtx = &xt * &x;
+        let chol = Cholesky::new(xtx).ok_or_else(|| {
+            AnpassError::Other(
+                "Cholesky decomposition failed in PreparedFit".to_string(),
+            )
+        })?;
+        Ok(Self { chol, xt })
+    }
+
+    /// solve the prepared problem for `energies`, returning the coefficients
+    pub fn solve(&self, energies: &Dvec) -> Dvec {
+        self.chol.solve(&(&self.xt * energies))
+    }
 }
 
 /// Solve the [ordinary least
@@ -587,8 +3032,14 @@ impl Anpass {
 /// backward substitution as described
 /// [here](https://en.wikipedia.org/wiki/Numerical_methods_for_linear_least_squares#Inverting_the_matrix_of_the_normal_equations).
 /// If the Cholesky decomposition fails, fall back on the LU decomposition and
-/// inverting XᵀX directly.
-fn solve_least_squares(xtx: Dmat, xt: Dmat, y: &Dvec, x: Dmat) -> (Dvec, Dmat) {
+/// inverting XᵀX directly, and if that fails too, on the minimum-norm solution
+/// from `solve_svd`.
+fn solve_least_squares<T: na::RealField + Copy>(
+    xtx: na::DMatrix<T>,
+    xt: na::DMatrix<T>,
+    y: &na::DVector<T>,
+    x: na::DMatrix<T>,
+) -> (na::DVector<T>, na::DMatrix<T>) {
     if let Some(chol) = Cholesky::new(xtx) {
         let l = chol.l();
         let z = l.solve_lower_triangular(&(xt * y)).unwrap();
@@ -601,28 +3052,254 @@ fn solve_least_squares(xtx: Dmat, xt: Dmat, y: &Dvec, x: Dmat) -> (Dvec, Dmat) {
             eprintln!("mat = \n{xtx:.8}");
             eprintln!("Cholesky decomposition failed in solve_least_squares, trying LU");
         }
-        let inv = na::LU::new(xtx)
-            .try_inverse()
-            .expect("LU decomposition also failed");
+        let Some(inv) = na::LU::new(xtx).try_inverse() else {
+            if DEBUG {
+                eprintln!("LU decomposition also failed, trying SVD");
+            }
+            let rtol = T::default_epsilon()
+                * na::convert(x.nrows().max(x.ncols()) as f64);
+            return (solve_svd(&x, y, rtol), x);
+        };
         let a = inv * x.transpose();
         let f = a * y;
         (f, x)
     }
 }
 
+/// compute the minimum-norm least-squares solution to Xβ = y using the
+/// singular value decomposition of `x`, treating singular values smaller than
+/// `rtol` times the largest singular value as zero
+fn solve_svd<T: na::RealField + Copy>(
+    x: &na::DMatrix<T>,
+    y: &na::DVector<T>,
+    rtol: T,
+) -> na::DVector<T> {
+    let svd = x.clone().svd(true, true);
+    let tol = rtol * svd.singular_values.max();
+    svd.solve(y, tol).expect("SVD solve failed")
+}
+
+/// replace the leading integer field of `line` with `n`, keeping its width
+fn replace_count(line: &str, n: usize) -> String {
+    let trimmed = line.trim_start();
+    let end = trimmed.find(char::is_whitespace).unwrap_or(trimmed.len());
+    if trimmed[..end].parse::<i64>().is_err() {
+        return line.to_owned();
+    }
+    let width = line.len() - trimmed.len() + end;
+    format!("{n:width$}{}", &trimmed[end..])
+}
+
+/// parse `s` as an f64, accepting Fortran-style `D` or `d` exponents like
+/// `-1.234D+02` in addition to the usual `E` and `e`
+fn parse_fortran_f64(s: &str) -> Result<f64, std::num::ParseFloatError> {
+    if s.contains(['D', 'd']) {
+        s.replace(['D', 'd'], "E").parse()
+    } else {
+        s.parse()
+    }
+}
+
 /// try to invert `mat` using the Cholesky decomposition but fall back to LU
 /// decomposition if it fails
 fn invert(mat: &Dmat) -> Dmat {
+    try_invert(mat).expect("LU decomposition also failed")
+}
+
+/// invert `mat + μI`, first trying the current value of `mu` and then
+/// increasing it tenfold, but to no less than 1e-3 times the scale of `mat`,
+/// until the shifted matrix is positive definite. The scale is the largest
+/// magnitude on the diagonal of `mat`, or 1 if that is zero. `mu` is left at
+/// the shift that was used. returns None if no shift up to 1e12 times the
+/// scale works, which can only happen for a matrix containing NaN
+fn shifted_inverse(mat: &Dmat, mu: &mut f64) -> Option<Dmat> {
+    let scale = mat.diagonal().amax();
+    let scale = if scale > 0.0 { scale } else { 1.0 };
+    let eye = Dmat::identity(mat.nrows(), mat.ncols());
+    loop {
+        if let Some(chol) = na::Cholesky::new(mat + *mu * &eye) {
+            return Some(chol.inverse());
+        }
+        if *mu > 1e12 * scale {
+            return None;
+        }
+        *mu = (10.0 * *mu).max(1e-3 * scale);
+        if DEBUG {
+            eprintln!("shifting Hessian by μ = {:.3e}", *mu);
+        }
+    }
+}
+
+/// compute the pseudo-inverse V diag(1/λ) Vᵀ of the symmetric matrix `mat`
+/// from its eigendecomposition, dropping the eigenvalues smaller in magnitude
+/// than machine epsilon times the largest one. returns None if every
+/// eigenvalue is negligible
+fn eigen_inverse(mat: &Dmat) -> Option<Dmat> {
+    let na::SymmetricEigen {
+        eigenvectors: v,
+        eigenvalues: evals,
+    } = mat.clone().symmetric_eigen();
+    let cutoff = f64::EPSILON * evals.amax();
+    if evals.iter().all(|e| e.abs() <= cutoff) {
+        return None;
+    }
+    let inv = evals.map(|e| if e.abs() > cutoff { 1.0 / e } else { 0.0 });
+    Some(&v * Dmat::from_diagonal(&inv) * v.transpose())
+}
+
+/// evaluate the polynomial with `exponents` and `coeffs` at the point whose
+/// coordinates are produced by `x`. shared by `Anpass::eval`,
+/// `Anpass::eval_many`, and `Polynomial`
+fn poly_eval<'a, T, I>(
+    exponents: &na::DMatrix<i32>,
+    x: I,
+    coeffs: &na::DVector<T>,
+) -> T
+where
+    T: na::RealField + Copy,
+    I: Iterator<Item = &'a T> + Clone,
+{
+    let thr: T = na::convert(THR);
+    let mut sum = T::zero();
+    for (k, prod) in coeffs.iter().enumerate() {
+        let mut prod = *prod;
+        if prod.abs() < thr {
+            continue;
+        }
+        for (j, xi) in x.clone().enumerate() {
+            let ejk = exponents[(j, k)];
+            if ejk != 0 {
+                prod *= xi.powi(ejk);
+            }
+        }
+        sum += prod;
+    }
+    sum
+}
+
+/// compute the gradient of the polynomial with `exponents` and `coeffs` at
+/// `x`. shared by `Anpass` and `Polynomial`
+fn poly_grad<T: na::RealField + Copy>(
+    exponents: &na::DMatrix<i32>,
+    x: &na::DVector<T>,
+    coeffs: &na::DVector<T>,
+) -> na::DVector<T> {
+    let thr: T = na::convert(THR);
+    let (nvbl, nunk) = exponents.shape();
+    let mut grad = na::DVector::zeros(nvbl);
+    for i in 0..nvbl {
+        let mut sum = T::zero();
+        for j in 0..nunk {
+            let fij = exponents[(i, j)];
+            let mut coj = coeffs[j] * na::convert(fij as f64);
+            if coj.abs() < thr {
+                continue;
+            }
+            if fij != 1 {
+                coj *= x[i].powi(fij - 1);
+            }
+            for k in 0..nvbl {
+                let ekj = exponents[(k, j)];
+                if k != i && ekj != 0 {
+                    coj *= x[k].powi(ekj);
+                }
+            }
+            sum += coj;
+        }
+        grad[i] = sum;
+    }
+    grad
+}
+
+/// compute the Hessian of the polynomial with `exponents` and `coeffs` at
+/// `x`. shared by `Anpass` and `Polynomial`
+fn poly_hess<T: na::RealField + Copy>(
+    exponents: &na::DMatrix<i32>,
+    x: &na::DVector<T>,
+    coeffs: &na::DVector<T>,
+) -> na::DMatrix<T> {
+    let thr: T = na::convert(THR);
+    let (nvbl, nunk) = exponents.shape();
+    let mut hess = na::DMatrix::zeros(nvbl, nvbl);
+    for i in 0..nvbl {
+        for l in 0..=i {
+            let mut sum = T::zero();
+            if i != l {
+                // off-diagonal
+                for j in 0..nunk {
+                    let mut coj = coeffs[j];
+                    let eij = exponents[(i, j)];
+                    let elj = exponents[(l, j)];
+                    let fij: T = na::convert(eij as f64);
+                    let flj: T = na::convert(elj as f64);
+                    coj *= fij * flj;
+                    if coj.abs() < thr {
+                        continue;
+                    }
+                    if eij != 1 {
+                        coj *= x[i].powi(eij - 1);
+                    }
+                    if elj != 1 {
+                        coj *= x[l].powi(elj - 1);
+                    }
+                    for k in 0..nvbl {
+                        if k != i && k != l {
+                            let ekj = exponents[(k, j)];
+                            if ekj != 0 {
+                                coj *= x[k].powi(ekj);
+                            }
+                        }
+                    }
+                    sum += coj;
+                }
+                hess[(i, l)] = sum;
+                hess[(l, i)] = sum;
+            } else {
+                // diagonal
+                for j in 0..nunk {
+                    let mut coj = coeffs[j];
+                    let eij = exponents[(i, j)];
+                    let fij: T = na::convert(eij as f64);
+                    coj *= fij * (fij - T::one());
+                    if coj.abs() < thr {
+                        continue;
+                    }
+                    if eij != 2 {
+                        coj *= x[i].powi(eij - 2);
+                    }
+                    for k in 0..nvbl {
+                        if k != i {
+                            let ekj = exponents[(k, j)];
+                            if ekj != 0 {
+                                coj *= x[k].powi(ekj);
+                            }
+                        }
+                    }
+                    sum += coj;
+                }
+                hess[(i, l)] = sum;
+            }
+        }
+    }
+    hess
+}
+
+/// compute n! as a float, for the nonnegative exponents in `make9903`
+fn factorial(n: i32) -> f64 {
+    (1..=n).map(f64::from).product()
+}
+
+/// the fallible version of `invert`, returning `None` if both decompositions
+/// fail
+fn try_invert(mat: &Dmat) -> Option<Dmat> {
     match na::Cholesky::new(mat.clone()) {
-        Some(mat) => mat.inverse(),
+        Some(mat) => Some(mat.inverse()),
         None => {
             if DEBUG {
                 eprintln!("mat = \n{mat:.8}");
                 eprintln!("Cholesky decomposition failed, trying LU");
             }
-            na::LU::new(mat.clone())
-                .try_inverse()
-                .expect("LU decomposition also failed")
+            na::LU::new(mat.clone()).try_inverse()
         }
     }
 }
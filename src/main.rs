@@ -5,7 +5,7 @@ fn main() {
     let infile = args.get(1);
     let anpass = match infile {
         Some(s) => Anpass::load_file(s),
-        None => Anpass::load(std::io::stdin()),
+        None => Anpass::load(std::io::stdin().lock()),
     };
     let (f9903, bias, res, kind) = anpass.run().unwrap();
     println!("bias: {bias}");
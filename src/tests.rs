@@ -2,16 +2,50 @@ use std::io::BufRead;
 use std::io::BufReader;
 
 use approx::assert_abs_diff_eq;
+use approx::assert_relative_eq;
 use nalgebra as na;
 
 use crate::fc::Fc;
 use crate::Anpass;
+use crate::AnpassBuilder;
+use crate::AnpassError;
 use crate::Bias;
+use crate::FitResult;
+use crate::NewtonError;
+use crate::NewtonOptions;
+use crate::NewtonStep;
+use crate::Polynomial;
+use crate::PreparedFit;
 use crate::StatKind;
+use crate::StepMethod;
 
 type Dmat = na::DMatrix<f64>;
 type Dvec = na::DVector<f64>;
 
+/// an `Anpass` with the given data and exponents and no bias, weights,
+/// surfaces, or header
+fn fixture<T: na::Scalar>(
+    disps: na::DMatrix<T>,
+    energies: na::DVector<T>,
+    exponents: na::DMatrix<i32>,
+) -> Anpass<T> {
+    Anpass {
+        disps,
+        energies,
+        exponents,
+        bias: None,
+        weights: None,
+        surfaces: None,
+        header: String::new(),
+    }
+}
+
+/// an `Anpass` with no data points, for testing the methods that only use
+/// the polynomial defined by `exponents`
+fn poly(exponents: na::DMatrix<i32>) -> Anpass {
+    fixture(Dmat::zeros(0, exponents.nrows()), Dvec::zeros(0), exponents)
+}
+
 #[test]
 fn test_load() {
     let anpass = Anpass::load_file("testfiles/anpass.in");
@@ -129,6 +163,9 @@ fn test_load() {
             ],
         ),
         bias: None,
+        weights: None,
+        surfaces: None,
+        header: String::new(),
     };
     assert_abs_diff_eq!(anpass.disps, want.disps);
     assert_eq!(anpass.energies.len(), want.energies.len());
@@ -217,6 +254,41 @@ fn test_eval() {
     assert!((got - want).abs() < 1e-12);
 }
 
+#[test]
+fn test_into_polynomial() {
+    let anpass = Anpass::load_file("testfiles/c3h2.in");
+    let (coeffs, _) = anpass.fit();
+    let (x, _) = anpass.newton(&coeffs).unwrap();
+    let x = x.add_scalar(0.01);
+    let (e, g, h) = (
+        anpass.eval(&x, &coeffs),
+        anpass.grad(&x, &coeffs),
+        anpass.hess(&x, &coeffs),
+    );
+    let poly: Polynomial = anpass.clone().into_polynomial(coeffs.clone());
+    assert_eq!(poly.n_variables(), anpass.n_variables());
+    assert_eq!(poly.exponents, anpass.exponents);
+    assert_eq!(poly.coeffs, coeffs);
+    assert_eq!(poly.eval(&x), e);
+    assert_eq!(poly.grad(&x), g);
+    assert_eq!(poly.hess(&x), h);
+}
+
+#[test]
+fn test_eval_many() {
+    let anpass = Anpass::load_file("testfiles/c3h2.in");
+    let (coeffs, _) = anpass.fit();
+    let got = anpass.eval_many(&anpass.disps, &coeffs);
+    assert_eq!(got.len(), anpass.disps.nrows());
+    for (i, row) in anpass.disps.row_iter().enumerate() {
+        assert_eq!(got[i], anpass.eval(&row.transpose(), &coeffs));
+    }
+    let empty = Dmat::zeros(0, anpass.n_variables());
+    assert_eq!(anpass.eval_many(&empty, &coeffs).len(), 0);
+}
+
+/// load the reference force constants in `filename`, sorted into the
+/// canonical order returned by `make9903`
 fn load9903(filename: &str) -> Vec<Fc> {
     let f = std::fs::File::open(filename).unwrap();
     let lines = BufReader::new(f).lines().map_while(Result::ok);
@@ -224,6 +296,7 @@ fn load9903(filename: &str) -> Vec<Fc> {
     for line in lines {
         ret.push(line.parse::<Fc>().unwrap());
     }
+    ret.sort_by(|a, b| a.indices().cmp(b.indices()));
     ret
 }
 
@@ -258,6 +331,42 @@ fn test_bias() {
     assert_abs_diff_eq!(got.disps, want_disps);
 }
 
+#[test]
+fn test_bias_mut() {
+    let mut anpass = Anpass::load_file("testfiles/c3h2.in");
+    let (coeffs, _) = anpass.fit();
+    let (x, _) = anpass.newton(&coeffs).unwrap();
+    let bias = Bias {
+        disp: x.clone(),
+        energy: anpass.eval(&x, &coeffs),
+    };
+    let want = anpass.bias(&bias);
+    anpass.bias_mut(&bias);
+    assert_eq!(anpass, want);
+}
+
+#[test]
+fn test_unbias() {
+    let anpass = Anpass::load_file("testfiles/c3h2.in");
+    let (coeffs, _) = anpass.fit();
+    let (x, _) = anpass.newton(&coeffs).unwrap();
+    let bias = Bias {
+        disp: x.clone(),
+        energy: anpass.eval(&x, &coeffs),
+    };
+    let biased = anpass.bias(&bias);
+    let got = biased.unbias(&bias);
+    assert_abs_diff_eq!(got.disps, anpass.disps, epsilon = 1e-15);
+    assert_abs_diff_eq!(got.energies, anpass.energies, epsilon = 1e-15);
+
+    // the stationary point of the biased surface is at its origin, which
+    // maps back to the original stationary point
+    let (coeffs, _) = biased.fit();
+    let (y, _) = biased.newton(&coeffs).unwrap();
+    assert_abs_diff_eq!(bias.unbias_point(&y), x, epsilon = 1e-10);
+    assert_eq!(bias.unbias_point(&Dvec::zeros(x.len())), x);
+}
+
 struct FullTest<'a> {
     infile: &'a str,
     want_file: &'a str,
@@ -322,3 +431,2674 @@ fn test_full_long() {
     ];
     full_test(&tests);
 }
+
+#[test]
+fn test_coeff_sensitivity() {
+    for infile in ["testfiles/anpass.in", "testfiles/anpass_weights.in"] {
+        let anpass = Anpass::load_file(infile);
+        let point = 10;
+        let got = anpass.coeff_sensitivity(point);
+        let want = fd_sensitivity(&anpass, point);
+        assert_relative_eq!(got, want, epsilon = 1e-5, max_relative = 1e-8);
+    }
+}
+
+/// the sensitivity of the coefficients from `fit` to the energy of `point`, by
+/// central finite differences
+fn fd_sensitivity(anpass: &Anpass, point: usize) -> Dvec {
+    const H: f64 = 1e-6;
+    let mut plus = anpass.clone();
+    plus.energies[point] += H;
+    let mut minus = anpass.clone();
+    minus.energies[point] -= H;
+    (plus.fit().0 - minus.fit().0) / (2.0 * H)
+}
+
+#[test]
+fn test_dump_run() {
+    let dir = std::env::temp_dir()
+        .join(format!("rust-anpass-dump-run-{}", std::process::id()));
+    let dir = dir.to_str().unwrap();
+    let anpass = Anpass::load_file("testfiles/h2o.in");
+    anpass.dump_run(dir).unwrap();
+    // running again into the existing directory should also succeed
+    anpass.dump_run(dir).unwrap();
+
+    let read = |name| std::fs::read_to_string(format!("{dir}/{name}")).unwrap();
+    let input = read("input.anpass");
+    assert!(input.starts_with("!INPUT"));
+    let report = read("report.txt");
+    assert!(report.contains("WEIGHTED SUM OF SQUARED RESIDUALS"));
+    assert_eq!(report, anpass.run_report().unwrap().to_string());
+    assert_eq!(read("coeffs.txt").lines().count(), anpass.exponents.ncols());
+    let got: Vec<Fc> = read("fort.9903")
+        .lines()
+        .filter(|l| !l.is_empty())
+        .map(|l| l.parse().unwrap())
+        .collect();
+    let want = load9903("testfiles/h2o.9903");
+    assert_abs_diff_eq!(got[..], want, epsilon = 9e-9);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_line_scan() {
+    let anpass = Anpass::load_file("testfiles/c3h2.in");
+    let (coeffs, _) = anpass.fit();
+    let (b, _) = anpass.newton(&coeffs).unwrap();
+    let a = Dvec::repeat(anpass.n_variables(), 0.01);
+    let (ts, energies) = anpass.line_scan(&coeffs, &a, &b, 11);
+    assert_eq!(ts.len(), 11);
+    assert_eq!(ts[0], 0.0);
+    assert_abs_diff_eq!(ts[10], 1.0);
+    assert_abs_diff_eq!(ts[5], 0.5);
+    assert_abs_diff_eq!(energies[0], anpass.eval(&a, &coeffs), epsilon = 1e-14);
+    assert_abs_diff_eq!(
+        energies[10],
+        anpass.eval(&b, &coeffs),
+        epsilon = 1e-14
+    );
+}
+
+#[test]
+fn test_load_sparse() {
+    let got = Anpass::load_file("testfiles/anpass_sparse.in");
+    let want = Anpass::load_file("testfiles/anpass.in");
+    assert_eq!(got, want);
+}
+
+#[test]
+fn test_load_sparse_malformed() {
+    let input = std::fs::read_to_string("testfiles/anpass_sparse.in").unwrap();
+    let parse = |s: String| Anpass::parse(s.as_bytes()).unwrap_err();
+    let field = |line, field: &str| AnpassError::InvalidField {
+        line,
+        field: field.to_owned(),
+    };
+    // line 87 is `1:1 2:1`
+    let bad = input.replacen("1:1 2:1\n", "1:1 2-1\n", 1);
+    assert_eq!(parse(bad), field(87, "2-1"));
+    let bad = input.replacen("1:1 2:1\n", "1:1 x:1\n", 1);
+    assert_eq!(parse(bad), field(87, "x:1"));
+    let bad = input.replacen("1:1 2:1\n", "1:1 2:y\n", 1);
+    assert_eq!(parse(bad), field(87, "2:y"));
+    let bad = input.replacen("1:1 2:1\n", "1:1 0:2\n", 1);
+    assert_eq!(parse(bad), field(87, "0:2"));
+    let bad = input.replacen("1:1 2:1\n", "1:1 4:1\n", 1);
+    assert_eq!(parse(bad), field(87, "4:1"));
+    let bad = input.replacen("1:1 2:1\n", "1:1 1:1\n", 1);
+    assert_eq!(parse(bad), field(87, "1:1"));
+
+    let bad = input.replace("UNKNOWNS\n  22", "UNKNOWNS\n  21");
+    assert_eq!(
+        parse(bad).to_string(),
+        "22 sparse unknowns given, but UNKNOWNS declares 21"
+    );
+}
+
+#[test]
+fn test_t_quantile() {
+    use crate::stats::t_quantile;
+    // reference values from standard t tables
+    assert_abs_diff_eq!(t_quantile(0.975, 1.0), 12.7062, epsilon = 1e-4);
+    assert_abs_diff_eq!(t_quantile(0.975, 10.0), 2.2281, epsilon = 1e-4);
+    assert_abs_diff_eq!(t_quantile(0.95, 30.0), 1.6973, epsilon = 1e-4);
+    assert_abs_diff_eq!(t_quantile(0.5, 5.0), 0.0, epsilon = 1e-6);
+}
+
+#[test]
+fn test_prediction_interval() {
+    let anpass = Anpass::load_file("testfiles/anpass.in");
+    let (coeffs, x) = anpass.fit();
+    let (n, p) = x.shape();
+    let sigma2 = anpass.sum_squared_residuals(&coeffs, &x) / (n - p) as f64;
+    let cov = sigma2 * (x.transpose() * &x).try_inverse().unwrap();
+
+    let mut last = 0.0;
+    for r in [0.0, 0.01, 0.02, 0.04] {
+        let x_new = na::dvector![r, r, r];
+        let (lo, hi) =
+            anpass.prediction_interval(&x_new, &coeffs, &cov, sigma2, 0.95);
+        let pred = anpass.eval(&x_new, &coeffs);
+        assert!(lo < pred && pred < hi);
+        assert_abs_diff_eq!(pred - lo, hi - pred, epsilon = 1e-12);
+        let width = hi - lo;
+        assert!(width > last, "{width} <= {last} at r = {r}");
+        last = width;
+    }
+
+    // with no more points than unknowns there are no degrees of freedom
+    for n in [10, 22] {
+        let few = Anpass {
+            disps: anpass.disps.rows(0, n).into_owned(),
+            energies: anpass.energies.rows(0, n).into_owned(),
+            ..anpass.clone()
+        };
+        let (lo, hi) = few.prediction_interval(
+            &na::dvector![0.0, 0.0, 0.0],
+            &coeffs,
+            &cov,
+            sigma2,
+            0.95,
+        );
+        assert!(lo.is_nan() && hi.is_nan());
+    }
+}
+
+#[test]
+fn test_update_fit_add_point() {
+    let full = Anpass::load_file("testfiles/anpass.in");
+    let n = full.disps.nrows();
+    let anpass = Anpass {
+        disps: full.disps.rows(0, n - 1).into_owned(),
+        energies: full.energies.rows(0, n - 1).into_owned(),
+        ..full.clone()
+    };
+    let (coeffs, x) = anpass.fit();
+    let inv = (x.transpose() * &x).try_inverse().unwrap();
+    let new_disp: Vec<_> = full.disps.row(n - 1).iter().cloned().collect();
+    let (got_inv, got) = anpass.update_fit_add_point(
+        &inv,
+        &coeffs,
+        &new_disp,
+        full.energies[n - 1],
+    );
+
+    let (want, x) = full.fit();
+    let want_inv = (x.transpose() * &x).try_inverse().unwrap();
+    assert!((got_inv - &want_inv).norm() / want_inv.norm() < 1e-10);
+    assert_abs_diff_eq!(got, want, epsilon = 1e-8);
+}
+
+#[test]
+fn test_sampling_levels() {
+    let anpass = Anpass::load_file("testfiles/anpass.in");
+    assert_eq!(anpass.sampling_levels(1e-8), vec![9, 9, 5]);
+    // a tolerance wider than the grid spacing merges neighboring levels
+    assert_eq!(anpass.sampling_levels(0.006), vec![5, 5, 5]);
+}
+
+#[test]
+fn test_verify_hessian() {
+    let anpass = Anpass::load_file("testfiles/c3h2.in");
+    let (coeffs, _) = anpass.fit();
+    let (x, _) = anpass.newton(&coeffs).unwrap();
+    anpass.verify_hessian(&x, &coeffs, 1e-5, 1e-6).unwrap();
+
+    let mut bad = anpass.hess(&x, &coeffs);
+    bad[(1, 0)] += 1e-3;
+    assert!(anpass
+        .compare_hessian(&x, &coeffs, &bad, 1e-5, 1e-6)
+        .is_err());
+}
+
+#[test]
+fn test_check_derivatives() {
+    let anpass = Anpass::load_file("testfiles/c3h2.in");
+    let (coeffs, _) = anpass.fit();
+    let (x, _) = anpass.newton(&coeffs).unwrap();
+    let x = x.add_scalar(0.01);
+    assert!(anpass.check_gradient(&x, &coeffs, 1e-4) < 1e-6);
+    let h = anpass.check_hessian(&x, &coeffs, 1e-4);
+    assert!(h < 1e-6);
+    assert!(anpass.verify_hessian(&x, &coeffs, 1e-4, h).is_ok());
+}
+
+#[test]
+fn test_descent_path() {
+    // f = x² + 2y² - 0.2x + 0.2y with a minimum at (0.1, -0.05)
+    let anpass =
+        poly(na::DMatrix::from_row_slice(2, 4, &[1, 0, 2, 0, 0, 1, 0, 2]));
+    let coeffs = na::dvector![-0.2, 0.2, 1.0, 2.0];
+    let start = na::dvector![0.5, 0.5];
+    let path = anpass.descent_path(&coeffs, &start, 0.1, 1000);
+    assert_eq!(path[0], start);
+    assert!(path.len() < 1000);
+    assert_abs_diff_eq!(
+        path.last().unwrap(),
+        &na::dvector![0.1, -0.05],
+        epsilon = 1e-10
+    );
+    // the energy decreases monotonically along the path
+    for w in path.windows(2) {
+        assert!(
+            anpass.eval(&w[1], &coeffs) <= anpass.eval(&w[0], &coeffs) + 1e-15
+        );
+    }
+}
+
+#[test]
+fn test_load_weights() {
+    let anpass = Anpass::load_file("testfiles/anpass_weights.in");
+    let weights = anpass.weights.as_ref().unwrap();
+    assert_eq!(weights.len(), 69);
+    assert_eq!(weights[0], 2.0);
+    assert_eq!(weights[1], 1.0);
+    assert_eq!(weights[68], 3.0);
+
+    // integer weights are equivalent to repeating the weighted points
+    let plain = Anpass::load_file("testfiles/anpass.in");
+    let mut disps = Vec::new();
+    let mut energies = Vec::new();
+    for (i, w) in weights.iter().enumerate() {
+        for _ in 0..*w as usize {
+            disps.extend(plain.disps.row(i).iter());
+            energies.push(plain.energies[i]);
+        }
+    }
+    let repeated = Anpass {
+        disps: Dmat::from_row_slice(energies.len(), 3, &disps),
+        energies: Dvec::from(energies),
+        ..plain.clone()
+    };
+    let (got, x) = anpass.fit();
+    let (want, _) = repeated.fit();
+    assert_eq!(x.nrows(), 69);
+    assert_abs_diff_eq!(got, want, epsilon = 1e-9);
+    let (unweighted, _) = plain.fit();
+    assert!((got - unweighted).norm() > 1e-6);
+
+    // and the weights survive a round trip through Display
+    let round = Anpass::load(anpass.to_string().as_bytes());
+    assert_eq!(round.weights, anpass.weights);
+
+    // weights accept Fortran D exponents, like the displacements and energies
+    let input = std::fs::read_to_string("testfiles/anpass_weights.in").unwrap();
+    let fortran = input.replacen("2.000000000000", "0.200000000000D+01", 1);
+    assert_ne!(fortran, input);
+    assert_eq!(Anpass::load(fortran.as_bytes()).weights, anpass.weights);
+}
+
+#[test]
+fn test_load_weights_malformed() {
+    let input = std::fs::read_to_string("testfiles/anpass_weights.in").unwrap();
+    let bad = input.replacen("WEIGHTS\n      2.000000000000\n", "WEIGHTS\n", 1);
+    assert_eq!(
+        Anpass::parse(bad.as_bytes()).unwrap_err().to_string(),
+        "68 weights given for 69 displacements"
+    );
+    let bad = input.replacen("      2.000000000000", "     -2.000000000000", 1);
+    assert_eq!(
+        Anpass::parse(bad.as_bytes()).unwrap_err(),
+        AnpassError::InvalidField {
+            line: 81,
+            field: "-2.000000000000".to_owned()
+        }
+    );
+}
+
+#[test]
+fn test_bias_to_min() {
+    let anpass = Anpass::load_file("testfiles/c3h2.in");
+    let bias = anpass.bias_to_min().unwrap();
+    let biased = anpass.bias(&bias);
+    let imin = biased.energies.imin();
+    assert_eq!(biased.energies[imin], 0.0);
+    assert!(biased.energies.iter().all(|&e| e >= 0.0));
+    assert_eq!(biased.disps.row(imin).norm(), 0.0);
+
+    let empty = Anpass {
+        energies: Dvec::zeros(0),
+        ..anpass
+    };
+    assert!(empty.bias_to_min().is_none());
+}
+
+#[test]
+fn test_prepared_fit() {
+    let anpass = Anpass::load_file("testfiles/anpass.in");
+    let prepared = PreparedFit::new(&anpass).unwrap();
+    for scale in [1.0, 0.5, 2.0] {
+        let energies = &anpass.energies * scale
+            + anpass.disps.column(0).map(|x| x * x * x);
+        let want = Anpass {
+            energies: energies.clone(),
+            ..anpass.clone()
+        }
+        .fit()
+        .0;
+        let got = prepared.solve(&energies);
+        assert_abs_diff_eq!(got, want, epsilon = 1e-9);
+    }
+}
+
+/// a simple linear congruential generator for reproducible noise in tests,
+/// returning values in [-0.5, 0.5)
+fn lcg(state: &mut u64) -> f64 {
+    *state = state
+        .wrapping_mul(6364136223846793005)
+        .wrapping_add(1442695040888963407);
+    (*state >> 11) as f64 / (1u64 << 53) as f64 - 0.5
+}
+
+/// a 2D grid of displacements with energies from 1 + 2x + 3y² plus a little
+/// noise, fit with the additional spurious term xy
+fn noisy_quadratic() -> Anpass {
+    let mut state = 42;
+    let mut disps = Vec::new();
+    let mut energies = Vec::new();
+    for i in -3..=3 {
+        for j in -3..=3 {
+            let (x, y) = (0.02 * i as f64, 0.02 * j as f64);
+            disps.extend([x, y]);
+            energies.push(1.0 + 2.0 * x + 3.0 * y * y + 1e-4 * lcg(&mut state));
+        }
+    }
+    fixture(
+        Dmat::from_row_slice(energies.len(), 2, &disps),
+        Dvec::from(energies),
+        na::DMatrix::from_row_slice(2, 4, &[0, 1, 0, 1, 0, 0, 2, 1]),
+    )
+}
+
+#[test]
+fn test_f32() {
+    let anpass = noisy_quadratic();
+    let single: Anpass<f32> = fixture(
+        anpass.disps.clone().cast(),
+        anpass.energies.clone().cast(),
+        anpass.exponents.clone(),
+    );
+    let (want, _) = anpass.fit();
+    let (got, x) = single.fit();
+    assert_eq!(x.shape(), anpass.build_design_matrix().shape());
+    assert_abs_diff_eq!(got, want.clone().cast::<f32>(), epsilon = 1e-3);
+    let full = single.fit_full();
+    assert_eq!(full.coeffs, got);
+    assert_abs_diff_eq!(
+        full.rmsd,
+        anpass.fit_full().rmsd as f32,
+        epsilon = 1e-5
+    );
+
+    let p = na::dvector![0.01f32, -0.03];
+    let q: Dvec = p.clone().cast();
+    assert_abs_diff_eq!(
+        single.eval(&p, &got),
+        anpass.eval(&q, &want) as f32,
+        epsilon = 1e-4
+    );
+    assert_abs_diff_eq!(
+        single.grad(&p, &got),
+        anpass.grad(&q, &want).cast::<f32>(),
+        epsilon = 1e-2
+    );
+    assert_abs_diff_eq!(
+        single.hess(&p, &got),
+        anpass.hess(&q, &want).cast::<f32>(),
+        epsilon = 1e-1
+    );
+}
+
+#[test]
+fn test_ill_determined_terms() {
+    let anpass = noisy_quadratic();
+    let (coeffs, x) = anpass.fit();
+    assert_eq!(anpass.ill_determined_terms(&coeffs, &x, 0.5), vec![3]);
+}
+
+#[test]
+fn test_third_derivatives() {
+    let anpass = Anpass::load_file("testfiles/c3h2.in");
+    let (coeffs, _) = anpass.fit();
+    let (x, _) = anpass.newton(&coeffs).unwrap();
+    let x = x.add_scalar(0.001);
+    let got = anpass.third_derivatives(&x, &coeffs);
+    let n = anpass.n_variables();
+    assert_eq!(got.len(), n * n * n);
+
+    const H: f64 = 1e-5;
+    for k in 0..n {
+        let mut xp = x.clone();
+        xp[k] += H;
+        let mut xm = x.clone();
+        xm[k] -= H;
+        let fd =
+            (anpass.hess(&xp, &coeffs) - anpass.hess(&xm, &coeffs)) / (2.0 * H);
+        for i in 0..n {
+            for j in 0..n {
+                assert_abs_diff_eq!(
+                    got[(i * n + j) * n + k],
+                    fd[(i, j)],
+                    epsilon = 1e-6
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn test_third_derivatives_low_order() {
+    // terms below third order contribute nothing
+    let anpass = noisy_quadratic();
+    let (coeffs, _) = anpass.fit();
+    let n = anpass.n_variables();
+    let x = Dvec::repeat(n, 0.1);
+    assert!(anpass
+        .third_derivatives(&x, &coeffs)
+        .iter()
+        .all(|&d| d == 0.0));
+
+    // f = 1 + x + x²y, whose only nonzero third derivative is ∂³f/∂x²∂y = 2
+    let anpass = poly(na::DMatrix::from_row_slice(2, 3, &[0, 1, 2, 0, 0, 1]));
+    let coeffs = na::dvector![1.0, 1.0, 1.0];
+    let got = anpass.third_derivatives(&na::dvector![0.3, -0.7], &coeffs);
+    assert_eq!(got, vec![0.0, 2.0, 2.0, 0.0, 2.0, 0.0, 0.0, 0.0]);
+}
+
+#[test]
+fn test_total_degree() {
+    let anpass = Anpass::load_file("testfiles/c3h2.in");
+    assert_eq!(anpass.total_degree(), 4);
+    let anpass = noisy_quadratic();
+    assert_eq!(anpass.total_degree(), 2);
+}
+
+#[test]
+fn test_train_test_split() {
+    let anpass = Anpass::load_file("testfiles/c3h2.in");
+    let n = anpass.disps.nrows();
+    let (train, test) = anpass.train_test_split(0.2, 1234);
+    assert_eq!(train.disps.nrows() + test.disps.nrows(), n);
+    assert_eq!(test.disps.nrows(), (n as f64 * 0.2).round() as usize);
+    assert_eq!(train.energies.len(), train.disps.nrows());
+    assert_eq!(train.exponents, anpass.exponents);
+
+    // every original point appears in exactly one of the two subsets
+    let rows = |a: &Anpass| -> Vec<Vec<u64>> {
+        a.disps
+            .row_iter()
+            .zip(a.energies.iter())
+            .map(|(r, e)| r.iter().chain([e]).map(|x| x.to_bits()).collect())
+            .collect()
+    };
+    let mut got = rows(&train);
+    got.extend(rows(&test));
+    got.sort();
+    let mut want = rows(&anpass);
+    want.sort();
+    assert_eq!(got, want);
+
+    let (train2, test2) = anpass.train_test_split(0.2, 1234);
+    assert_eq!(train2, train);
+    assert_eq!(test2, test);
+    let (_, test3) = anpass.train_test_split(0.2, 4321);
+    assert_ne!(test3, test);
+}
+
+#[test]
+fn test_max_residual() {
+    let mut anpass = Anpass::load_file("testfiles/anpass.in");
+    anpass.energies[17] += 1e-5;
+    let (coeffs, x) = anpass.fit();
+    let (i, resid) = anpass.max_residual(&coeffs, &x);
+    assert_eq!(i, 17);
+    // the raised energy is above the fitted surface
+    assert!(resid > 0.0);
+    assert_eq!(resid, anpass.point_residuals(&coeffs)[17]);
+    assert!(
+        resid.abs() * resid.abs() <= anpass.sum_squared_residuals(&coeffs, &x)
+    );
+}
+
+/// f = 2/x + 3xy⁻² + y
+fn rational() -> (Anpass, Dvec) {
+    let anpass = poly(na::DMatrix::from_row_slice(2, 3, &[-1, 1, 0, 0, -2, 1]));
+    (anpass, na::dvector![2.0, 3.0, 1.0])
+}
+
+#[test]
+fn test_negative_exponents() {
+    let (anpass, coeffs) = rational();
+    let (x, y) = (1.5, 0.5);
+    let p = na::dvector![x, y];
+    let want = 2.0 / x + 3.0 * x / (y * y) + y;
+    assert_abs_diff_eq!(anpass.eval(&p, &coeffs), want, epsilon = 1e-12);
+
+    let want = na::dvector![
+        -2.0 / (x * x) + 3.0 / (y * y),
+        -6.0 * x / (y * y * y) + 1.0
+    ];
+    assert_abs_diff_eq!(anpass.grad(&p, &coeffs), want, epsilon = 1e-12);
+
+    let want = Dmat::from_row_slice(
+        2,
+        2,
+        &[
+            4.0 / (x * x * x),
+            -6.0 / (y * y * y),
+            -6.0 / (y * y * y),
+            18.0 * x / (y * y * y * y),
+        ],
+    );
+    assert_abs_diff_eq!(anpass.hess(&p, &coeffs), want, epsilon = 1e-12);
+}
+
+#[test]
+fn test_make9903_with() {
+    let anpass = Anpass::load_file("testfiles/c3h2.in");
+    let (coeffs, _) = anpass.fit();
+    let want = anpass.make9903(&coeffs);
+    assert_eq!(anpass.make9903_with(&coeffs, crate::FAC), want);
+    let got = anpass.make9903_with(&coeffs, 1.0);
+    assert_eq!(got.len(), want.len());
+    for (g, w) in got.iter().zip(&want) {
+        assert_eq!(g.indices(), w.indices());
+        assert_abs_diff_eq!(g.value() * crate::FAC, w.value(), epsilon = 1e-12);
+    }
+}
+
+#[test]
+fn test_make9903_filtered() {
+    let anpass = Anpass::load_file("testfiles/c3h2.in");
+    let (mut coeffs, _) = anpass.fit();
+    coeffs[0] = 0.0;
+    coeffs[3] = 1e-12;
+    let all = anpass.make9903(&coeffs);
+    assert_eq!(anpass.make9903_filtered(&coeffs, 0.0), all);
+    let got = anpass.make9903_filtered(&coeffs, crate::THR);
+    assert_eq!(got.len(), all.len() - 2);
+    assert!(got.iter().all(|fc| fc.value().abs() >= crate::THR));
+    let want: Vec<_> = all
+        .into_iter()
+        .filter(|fc| fc.value().abs() >= crate::THR)
+        .collect();
+    assert_eq!(got, want);
+}
+
+#[test]
+fn test_make9903_sorted() {
+    let anpass = Anpass::load_file("testfiles/c3h2.in");
+    let (coeffs, _) = anpass.fit();
+    let want = anpass.make9903(&coeffs);
+    assert!(want.windows(2).all(|w| w[0].indices() < w[1].indices()));
+
+    // reversing the order of the terms does not change the output
+    let mut reversed = anpass.clone();
+    let n = anpass.exponents.ncols();
+    for j in 0..n {
+        reversed
+            .exponents
+            .set_column(j, &anpass.exponents.column(n - 1 - j));
+    }
+    let coeffs = Dvec::from_iterator(n, coeffs.iter().rev().cloned());
+    assert_eq!(reversed.make9903(&coeffs), want);
+}
+
+#[test]
+fn test_make9903_duplicates() {
+    // f = xy + 2xy + y², where the first two terms are the same derivative
+    let anpass = poly(na::DMatrix::from_row_slice(2, 3, &[1, 1, 0, 1, 1, 2]));
+    let coeffs = na::dvector![1.0, 2.0, 1.0];
+    let got = anpass.make9903_with(&coeffs, 1.0);
+    let want = vec![Fc::new(2, 1, 0, 0, 3.0), Fc::new(2, 2, 0, 0, 2.0)];
+    assert_eq!(got, want);
+}
+
+#[test]
+fn test_make9903_quintic() {
+    // f = x + x² + x³ + x⁴ + x⁵ + x²y³, with two fifth-order terms
+    let anpass = poly(na::DMatrix::from_row_slice(
+        2,
+        6,
+        &[1, 2, 3, 4, 5, 2, 0, 0, 0, 0, 0, 3],
+    ));
+    let coeffs = Dvec::repeat(6, 1.0);
+    let got = anpass.make9903(&coeffs);
+    assert_eq!(got.len(), 6);
+    for (i, fc) in got[..5].iter().enumerate() {
+        let order = i + 1;
+        assert_eq!(fc.order(), order);
+        assert_eq!(fc.indices().len(), order.max(4));
+        assert!(fc.indices()[..order].iter().all(|&i| i == 1));
+        assert_abs_diff_eq!(
+            fc.value(),
+            crate::factorial(order as i32) * crate::FAC,
+            epsilon = 1e-12
+        );
+    }
+    assert_eq!(
+        got[5],
+        Fc::from_indices(vec![2, 2, 2, 1, 1], 12.0 * crate::FAC)
+    );
+
+    // the extra indices survive a round trip through the fort.9903 format
+    let line = got[5].to_string();
+    assert_eq!(line.parse::<Fc>().unwrap().indices(), got[5].indices());
+}
+
+#[test]
+#[should_panic(expected = "negative exponents")]
+fn test_negative_exponents_9903() {
+    let (anpass, coeffs) = rational();
+    anpass.make9903(&coeffs);
+}
+
+#[test]
+fn test_jackknife_stationary() {
+    let anpass = Anpass::load_file("testfiles/c3h2.in");
+    let (coeffs, _) = anpass.fit();
+    let (want, _) = anpass.newton(&coeffs).unwrap();
+    let got = anpass.jackknife_stationary(10, 7).unwrap();
+    assert_eq!(got.len(), 10);
+    for x in &got {
+        assert!((x - &want).norm() < 1e-6);
+    }
+    assert_eq!(got, anpass.jackknife_stationary(10, 7).unwrap());
+
+    // with n at least the number of points, every point is left out exactly
+    // once, so the results are the leave-one-out minima of a noisy parabola
+    let mut state = 3;
+    let disps: Vec<_> = (-3..=3).map(|i| 0.1 * i as f64).collect();
+    let energies = disps
+        .iter()
+        .map(|x| x * x - 0.1 * x + 1e-3 * lcg(&mut state))
+        .collect::<Vec<_>>();
+    let anpass = fixture(
+        Dmat::from_column_slice(7, 1, &disps),
+        Dvec::from(energies),
+        na::DMatrix::from_row_slice(1, 3, &[0, 1, 2]),
+    );
+    let sorted = |mut v: Vec<Dvec>| {
+        v.sort_by(|a, b| a[0].partial_cmp(&b[0]).unwrap());
+        v
+    };
+    let want = sorted(
+        (0..7)
+            .map(|skip| {
+                let keep: Vec<_> = (0..7).filter(|&i| i != skip).collect();
+                let few = anpass.select_points(&keep);
+                let (coeffs, _) = few.fit();
+                few.newton(&coeffs).unwrap().0
+            })
+            .collect(),
+    );
+    assert!(want.windows(2).all(|w| w[0] != w[1]));
+    let got = anpass.jackknife_stationary(100, 7).unwrap();
+    assert_eq!(got.len(), 7);
+    assert_eq!(sorted(got), want);
+}
+
+#[test]
+fn test_transpose_exponents() {
+    let anpass = Anpass::load_file("testfiles/anpass.in");
+    let t = anpass.transpose_exponents();
+    assert_eq!(t.exponents.shape(), (22, 3));
+    assert_eq!(t.exponents[(5, 1)], anpass.exponents[(1, 5)]);
+    assert_eq!(t.transpose_exponents(), anpass);
+}
+
+#[test]
+fn test_f_statistic() {
+    let anpass = noisy_quadratic();
+    let (coeffs, x) = anpass.fit();
+    assert!(anpass.f_statistic(&coeffs, &x) > 1e4);
+
+    let mut state = 7;
+    let random = Anpass {
+        energies: anpass.energies.map(|_| lcg(&mut state)),
+        ..anpass.clone()
+    };
+    let (coeffs, x) = random.fit();
+    assert!(random.f_statistic(&coeffs, &x) < 5.0);
+
+    let rows = [0, 9, 30, 44];
+    let few = Anpass {
+        disps: anpass.disps.select_rows(&rows),
+        energies: anpass.energies.select_rows(&rows),
+        ..anpass.clone()
+    };
+    let (coeffs, x) = few.fit();
+    assert!(few.f_statistic(&coeffs, &x).is_nan());
+
+    // without a constant term the statistic is undefined
+    let no_constant = Anpass {
+        exponents: anpass.exponents.columns(1, 3).into_owned(),
+        ..anpass.clone()
+    };
+    let (coeffs, x) = no_constant.fit();
+    assert!(no_constant.f_statistic(&coeffs, &x).is_nan());
+
+    // and so it is without any unknowns
+    let empty = Anpass {
+        exponents: na::DMatrix::zeros(2, 0),
+        ..anpass.clone()
+    };
+    let x = empty.build_design_matrix();
+    assert!(empty.f_statistic(&Dvec::zeros(0), &x).is_nan());
+}
+
+#[test]
+fn test_labeled_coeffs() {
+    let anpass = Anpass::load_file("testfiles/anpass.in");
+    let (coeffs, _) = anpass.fit();
+    let got = anpass.labeled_coeffs(&coeffs);
+    assert_eq!(got.len(), 22);
+    assert_eq!(got[0], (vec![0, 0, 0], coeffs[0]));
+    assert_eq!(got[19], (vec![1, 1, 2], coeffs[19]));
+    for (k, (exps, c)) in got.iter().enumerate() {
+        assert_eq!(exps[..], anpass.exponents.column(k).as_slice()[..]);
+        assert_eq!(*c, coeffs[k]);
+    }
+}
+
+#[test]
+fn test_origin_hessian_singular() {
+    // f = x² + y⁴ is flat to second order along y
+    let anpass = poly(na::DMatrix::from_row_slice(2, 3, &[2, 0, 0, 0, 2, 4]));
+    assert!(anpass.origin_hessian_singular(&na::dvector![1.0, 0.0, 1.0], 1e-8));
+    assert!(!anpass.origin_hessian_singular(&na::dvector![1.0, 1.0, 1.0], 1e-8));
+
+    let anpass = Anpass::load_file("testfiles/c3h2.in");
+    let (coeffs, _) = anpass.fit();
+    assert!(!anpass.origin_hessian_singular(&coeffs, 1e-8));
+}
+
+#[test]
+fn test_relative_energies() {
+    // f = x⁴ - x² + 0.1x is a tilted double well
+    let anpass = poly(na::DMatrix::from_row_slice(1, 3, &[1, 2, 4]));
+    let coeffs = na::dvector![0.1, -1.0, 1.0];
+    let left = anpass.descent_path(&coeffs, &na::dvector![-1.0], 0.05, 10000);
+    let right = anpass.descent_path(&coeffs, &na::dvector![1.0], 0.05, 10000);
+    let minima = [right.last().unwrap().clone(), left.last().unwrap().clone()];
+    assert!(minima[0][0] > 0.5 && minima[1][0] < -0.5);
+
+    let got = anpass.relative_energies(&coeffs, &minima);
+    let f = |x: f64| x.powi(4) - x * x + 0.1 * x;
+    let want = f(minima[0][0]) - f(minima[1][0]);
+    assert_eq!(got[1], 0.0);
+    assert_abs_diff_eq!(got[0], want, epsilon = 1e-12);
+    // the well tilted upward by the linear term is higher by about 0.14
+    assert_abs_diff_eq!(got[0], 0.14, epsilon = 0.01);
+}
+
+#[test]
+fn test_format_field_mismatch() {
+    let input = std::fs::read_to_string("testfiles/anpass.in").unwrap();
+    let bad = input.replace("(3F12.8,f20.12)", "(2F12.8,f20.12)");
+    let got = Anpass::parse(bad.as_bytes()).unwrap_err();
+    assert_eq!(
+        got,
+        AnpassError::FormatFieldMismatch {
+            line: 11,
+            declared: 2,
+            found: 4
+        }
+    );
+    assert!(Anpass::parse(input.as_bytes()).is_ok());
+}
+
+#[test]
+fn test_try_load() {
+    assert_eq!(
+        Anpass::try_load("testfiles/anpass.in").unwrap(),
+        Anpass::load_file("testfiles/anpass.in")
+    );
+    assert!(matches!(
+        Anpass::try_load("testfiles/missing.in"),
+        Err(AnpassError::Open { .. })
+    ));
+
+    let input = std::fs::read_to_string("testfiles/anpass.in").unwrap();
+    let bad = input.replace("(3F12.8,f20.12)", "");
+    assert_eq!(
+        Anpass::parse(bad.as_bytes()).unwrap_err(),
+        AnpassError::MissingFormatLine
+    );
+
+    let bad = input.replacen("-0.01500000", "-0.015OOOOO", 1);
+    assert_eq!(
+        Anpass::parse(bad.as_bytes()).unwrap_err(),
+        AnpassError::InvalidField {
+            line: 15,
+            field: "-0.015OOOOO".to_owned()
+        }
+    );
+
+    let bad = input.replacen(" 0.000124321064", "", 1);
+    assert_eq!(
+        Anpass::parse(bad.as_bytes()).unwrap_err(),
+        AnpassError::RowWidth {
+            line: 16,
+            expected: 4,
+            found: 3
+        }
+    );
+}
+
+#[test]
+fn test_row_width_mismatch() {
+    let input = std::fs::read_to_string("testfiles/anpass.in").unwrap();
+    // a row that lost its energy would otherwise shift every later row
+    let bad = input.replacen(
+        " -0.00500000 -0.01000000  0.00000000      0.000035977201",
+        " -0.00500000 -0.01000000  0.00000000",
+        1,
+    );
+    let got = Anpass::parse(bad.as_bytes()).unwrap_err();
+    assert_eq!(
+        got,
+        AnpassError::RowWidth {
+            line: 14,
+            expected: 4,
+            found: 3
+        }
+    );
+    assert_eq!(
+        got.to_string(),
+        "row ending on line 14 contains 3 fields, but 4 were expected"
+    );
+
+    // and so would an extra field
+    let bad = input.replacen("0.000048243883", "0.000048243883 1.0", 1);
+    assert_eq!(
+        Anpass::parse(bad.as_bytes()).unwrap_err(),
+        AnpassError::RowWidth {
+            line: 15,
+            expected: 4,
+            found: 5
+        }
+    );
+}
+
+#[test]
+fn test_malformed_input() {
+    let input = std::fs::read_to_string("testfiles/anpass.in").unwrap();
+    let parse = |s: String| Anpass::parse(s.as_bytes()).unwrap_err();
+
+    // without an UNKNOWNS section, the FUNCTION line is read as a data point
+    let bad = input.replace("UNKNOWNS\n  22\n", "");
+    assert_eq!(
+        parse(bad),
+        AnpassError::InvalidField {
+            line: 80,
+            field: "FUNCTION".to_owned()
+        }
+    );
+
+    // and with no function at all, the exponents are just empty
+    let end = input.find("UNKNOWNS").unwrap();
+    let template = Anpass::parse(&input.as_bytes()[..end]).unwrap();
+    assert_eq!(template.exponents.shape(), (3, 0));
+
+    let bad = input.replace("UNKNOWNS\n  22", "UNKNOWNS\n   0");
+    assert_eq!(
+        parse(bad),
+        AnpassError::RowWidth {
+            line: 83,
+            expected: 0,
+            found: 16
+        }
+    );
+
+    // an exponent count that is not a multiple of the number of unknowns
+    let bad = input.replace("UNKNOWNS\n  22", "UNKNOWNS\n  21");
+    assert_eq!(
+        parse(bad),
+        AnpassError::RowWidth {
+            line: 84,
+            expected: 21,
+            found: 22
+        }
+    );
+    let bad = input.replace("   2    2    2    4\n", "   2    2    2\n");
+    assert_eq!(
+        parse(bad),
+        AnpassError::RowWidth {
+            line: 88,
+            expected: 22,
+            found: 21
+        }
+    );
+
+    let bad = input.replacen(
+        "   1    0    2    1    0    0",
+        "   1    0    2    1    O    0",
+        1,
+    );
+    assert_eq!(
+        parse(bad),
+        AnpassError::InvalidField {
+            line: 83,
+            field: "O".to_owned()
+        }
+    );
+
+    let stat = input.replace(
+        "!STATIONARY POINT",
+        "STATIONARY POINT\n  0.001  0.002  0.003  -0.5",
+    );
+    let anpass = Anpass::parse(stat.as_bytes()).unwrap();
+    assert_eq!(anpass.bias.unwrap().energy, -0.5);
+
+    let bad = input.replace("!STATIONARY POINT", "STATIONARY POINT\n");
+    assert_eq!(
+        parse(bad),
+        AnpassError::RowWidth {
+            line: 92,
+            expected: 4,
+            found: 0
+        }
+    );
+    let bad = stat.replace("0.002", "0.OO2");
+    assert_eq!(
+        parse(bad),
+        AnpassError::InvalidField {
+            line: 92,
+            field: "0.OO2".to_owned()
+        }
+    );
+
+    let input = std::fs::read_to_string("testfiles/anpass_weights.in").unwrap();
+    let bad = input.replacen("      1.000000000000", "      1.OOOOOOOOOOOO", 1);
+    assert_eq!(
+        parse(bad),
+        AnpassError::InvalidField {
+            line: 82,
+            field: "1.OOOOOOOOOOOO".to_owned()
+        }
+    );
+}
+
+#[test]
+fn test_fit_about() {
+    let anpass = Anpass::load_file("testfiles/c3h2.in");
+    let (coeffs, _) = anpass.fit();
+    let (x, _) = anpass.newton(&coeffs).unwrap();
+    let origin = Dvec::zeros(anpass.n_variables());
+    assert!(anpass.grad(&origin, &coeffs).norm() > 1e-5);
+    let (coeffs, _) = anpass.fit_about(&x).unwrap();
+    assert!(anpass.grad(&origin, &coeffs).norm() < 1e-7);
+    assert!(anpass.fit_about(&Dvec::zeros(3)).is_err());
+}
+
+#[test]
+fn test_fc_round_trip() {
+    let line = "    3    3    1    1      8.562725561910";
+    let fc: Fc = line.parse().unwrap();
+    assert_eq!(fc, Fc::new(3, 3, 1, 1, 8.56272556191));
+    assert_eq!(fc.to_string(), line);
+
+    // every line of a reference fort.9903 file is reproduced exactly
+    let contents = std::fs::read_to_string("testfiles/h2o.9903").unwrap();
+    for line in contents.lines().filter(|l| !l.trim().is_empty()) {
+        assert_eq!(line.parse::<Fc>().unwrap().to_string(), line);
+    }
+}
+
+#[test]
+fn test_fc_counts() {
+    let fcs = [
+        Fc::new(0, 0, 0, 0, 1e-12),
+        Fc::new(1, 1, 0, 0, 8.36),
+        Fc::new(2, 1, 0, 0, 0.36),
+        Fc::new(1, 1, 1, 0, -41.6),
+        Fc::new(3, 3, 1, 1, 8.56),
+        Fc::new(2, 2, 1, 1, 0.71),
+        Fc::new(3, 3, 3, 3, 1.1),
+    ];
+    let got = crate::fc::fc_counts(&fcs);
+    let want =
+        std::collections::BTreeMap::from([(0, 1), (2, 2), (3, 1), (4, 3)]);
+    assert_eq!(got, want);
+
+    let anpass = Anpass::load_file("testfiles/h2o.in");
+    let (coeffs, _) = anpass.fit();
+    let fcs = anpass.make9903(&coeffs);
+    let got = crate::fc::fc_counts(&fcs);
+    assert_eq!(got.values().sum::<usize>(), fcs.len());
+}
+
+#[test]
+fn test_write9903_precision() {
+    let anpass = Anpass::load_file("testfiles/h2o.in");
+    let (coeffs, _) = anpass.fit();
+    let fcs = anpass.make9903(&coeffs);
+    let mut want = Vec::new();
+    anpass.write9903(&mut want, &fcs);
+    let mut got = Vec::new();
+    anpass.write9903_precision(&mut got, &fcs, 12);
+    assert_eq!(got, want);
+
+    let mut got = Vec::new();
+    anpass.write9903_precision(&mut got, &fcs, 15);
+    let got = String::from_utf8(got).unwrap();
+    let line = got.lines().nth(1).unwrap();
+    assert_eq!(line.len(), 4 * 5 + 15 + 8);
+    let (_, digits) = line.split_once('.').unwrap();
+    assert_eq!(digits.len(), 15);
+    let got = crate::fc::read9903(got.as_bytes()).unwrap();
+    assert!(crate::fc::compare9903(&got, &fcs, 1e-15).is_empty());
+}
+
+#[test]
+fn test_read9903() {
+    use crate::fc::{compare9903, read9903};
+    let anpass = Anpass::load_file("testfiles/h2o.in");
+    let (coeffs, _) = anpass.fit();
+    let fcs = anpass.make9903(&coeffs);
+    let mut buf = Vec::new();
+    anpass.write9903(&mut buf, &fcs);
+    let got = read9903(&buf[..]).unwrap();
+    assert_eq!(got.len(), fcs.len());
+    assert!(compare9903(&got, &fcs, 1e-12).is_empty());
+
+    let mut changed = got.clone();
+    changed[3].value += 1e-3;
+    let diffs = compare9903(&changed, &fcs, 1e-6);
+    assert_eq!(diffs.len(), 1);
+    assert_eq!(diffs[0].0, changed[3]);
+    assert_abs_diff_eq!(diffs[0].2, 1e-3, epsilon = 1e-12);
+
+    assert!(read9903("    1    1    0    0    oops\n".as_bytes()).is_err());
+}
+
+#[test]
+fn test_scale_coordinates() {
+    let anpass = Anpass::load_file("testfiles/anpass.in");
+    let scales = na::dvector![2.0, 0.5, 4.0];
+    let scaled = anpass.scale_coordinates(&scales);
+    assert_eq!(scaled.disps[(0, 0)], 2.0 * anpass.disps[(0, 0)]);
+    assert_eq!(scaled.disps[(0, 1)], 0.5 * anpass.disps[(0, 1)]);
+    assert_eq!(scaled.disps[(0, 2)], 4.0 * anpass.disps[(0, 2)]);
+    assert_eq!(scaled.energies, anpass.energies);
+    let back = scaled.scale_coordinates(&scales.map(|s| 1.0 / s));
+    assert_eq!(back, anpass);
+}
+
+#[test]
+fn test_estimated_fit_memory() {
+    let anpass = Anpass::load_file("testfiles/anpass.in");
+    assert_eq!(anpass.estimated_fit_memory(), 69 * 22 * 8 + 22 * 22 * 8);
+    // doubling the number of points doubles only the design matrix term
+    let rows: Vec<_> = (0..69).chain(0..69).collect();
+    let doubled = Anpass {
+        disps: anpass.disps.select_rows(&rows),
+        energies: anpass.energies.select_rows(&rows),
+        ..anpass.clone()
+    };
+    assert_eq!(
+        doubled.estimated_fit_memory() - anpass.estimated_fit_memory(),
+        69 * 22 * 8
+    );
+}
+
+#[test]
+fn test_fit_within_radius() {
+    let anpass = Anpass::load_file("testfiles/anpass.in");
+    const R: f64 = 0.0125;
+    let (coeffs, x) = anpass.fit_within_radius(R).unwrap();
+    let inner: Vec<_> = (0..anpass.disps.nrows())
+        .filter(|&i| anpass.disps.row(i).norm() < R)
+        .collect();
+    assert_eq!(x.nrows(), inner.len());
+    assert!(inner.len() < anpass.disps.nrows());
+
+    let inner = Anpass {
+        disps: anpass.disps.select_rows(&inner),
+        energies: anpass.energies.select_rows(&inner),
+        ..anpass.clone()
+    };
+    let (full, _) = anpass.fit();
+    assert!(
+        inner.sum_squared_residuals(&coeffs, &x)
+            < inner.sum_squared_residuals(&full, &x)
+    );
+
+    assert!(anpass.fit_within_radius(0.001).is_err());
+}
+
+#[test]
+fn test_fit_auto_ridge() {
+    let anpass = Anpass::load_file("testfiles/anpass.in");
+    let cond = |x: &Dmat, lambda: f64| {
+        let mut xtx = x.transpose() * x;
+        for i in 0..xtx.nrows() {
+            xtx[(i, i)] += lambda;
+        }
+        let evals = xtx.symmetric_eigenvalues();
+        evals.max() / evals.min()
+    };
+    const TARGET: f64 = 1e6;
+    let (coeffs, x, lambda) = anpass.fit_auto_ridge(TARGET);
+    assert!(cond(&x, 0.0) > TARGET);
+    assert!(lambda > 0.0);
+    assert_relative_eq!(cond(&x, lambda), TARGET, max_relative = 1e-6);
+    assert!(cond(&x, 0.99 * lambda) > TARGET);
+    let (unregularized, _) = anpass.fit();
+    assert!(coeffs.norm() < unregularized.norm());
+
+    // no regularization is needed for a loose enough target
+    let (coeffs, _, lambda) = anpass.fit_auto_ridge(1e20);
+    assert_eq!(lambda, 0.0);
+    assert_abs_diff_eq!(coeffs, unregularized, epsilon = 1e-9);
+}
+
+#[test]
+#[should_panic(expected = "target condition number must be greater than one")]
+fn test_fit_auto_ridge_bad_target() {
+    let anpass = Anpass::load_file("testfiles/anpass.in");
+    anpass.fit_auto_ridge(1.0);
+}
+
+#[test]
+fn test_hessian_asymmetry() {
+    let anpass = Anpass::load_file("testfiles/c3h2.in");
+    let (coeffs, _) = anpass.fit();
+    let x = Dvec::from_fn(anpass.n_variables(), |i, _| 0.001 * i as f64);
+    assert_eq!(anpass.hessian_asymmetry(&x, &coeffs), 0.0);
+}
+
+#[test]
+fn test_fcs_to_latex() {
+    let fcs = [
+        Fc::new(1, 1, 0, 0, 8.360863692412),
+        Fc::new(1, 1, 1, 0, -41.638868371768),
+        Fc::new(2, 1, 0, 0, 0.364250381719),
+    ];
+    let got = crate::fc::fcs_to_latex(&fcs, 4);
+    let want = r"\begin{tabular}{ccccr}
+\hline
+$i$ & $j$ & $k$ & $l$ & Value \\
+\hline
+\multicolumn{5}{l}{Quadratic} \\
+1 & 1 &  &  & 8.3609 \\
+2 & 1 &  &  & 0.3643 \\
+\multicolumn{5}{l}{Cubic} \\
+1 & 1 & 1 &  & -41.6389 \\
+\hline
+\end{tabular}
+";
+    assert_eq!(got, want);
+
+    let anpass = Anpass::load_file("testfiles/h2o.in");
+    let (coeffs, _) = anpass.fit();
+    let fcs = anpass.make9903(&coeffs);
+    let got = crate::fc::fcs_to_latex(&fcs, 6);
+    let groups = crate::fc::fc_counts(&fcs).len();
+    assert_eq!(got.matches("\\\\\n").count(), 1 + groups + fcs.len());
+}
+
+#[test]
+fn test_grad_hess() {
+    for infile in ["testfiles/c3h2.in", "testfiles/hoof.in"] {
+        let anpass = Anpass::load_file(infile);
+        let (coeffs, _) = anpass.fit();
+        for scale in [0.0, 0.001, -0.003] {
+            let x = Dvec::from_fn(anpass.n_variables(), |i, _| {
+                scale * (i as f64 + 1.0)
+            });
+            let (grad, hess) = anpass.grad_hess(&x, &coeffs);
+            assert_eq!(grad, anpass.grad(&x, &coeffs));
+            assert_eq!(hess, anpass.hess(&x, &coeffs));
+        }
+    }
+}
+
+#[test]
+#[ignore]
+fn bench_grad_hess() {
+    const N: usize = 100;
+    let anpass = Anpass::load_file("testfiles/c5h2.in");
+    let (coeffs, _) = anpass.fit();
+    let x = Dvec::repeat(anpass.n_variables(), 0.001);
+
+    let now = std::time::Instant::now();
+    for _ in 0..N {
+        std::hint::black_box(anpass.grad(&x, &coeffs));
+        std::hint::black_box(anpass.hess(&x, &coeffs));
+    }
+    let separate = now.elapsed();
+
+    let now = std::time::Instant::now();
+    for _ in 0..N {
+        std::hint::black_box(anpass.grad_hess(&x, &coeffs));
+    }
+    let combined = now.elapsed();
+    println!(
+        "separate: {:.3} ms/iter, combined: {:.3} ms/iter",
+        separate.as_secs_f64() * 1e3 / N as f64,
+        combined.as_secs_f64() * 1e3 / N as f64,
+    );
+}
+
+#[test]
+fn test_plain() {
+    let anpass = Anpass::load_file("testfiles/anpass.in");
+    let plain = anpass.to_plain();
+    assert_eq!(plain.disps.len(), 69);
+    assert_eq!(plain.disps[0], vec![-0.005, -0.005, -0.01]);
+    assert_eq!(plain.energies.len(), 69);
+    assert_eq!(plain.exponents.len(), 3);
+    assert_eq!(plain.exponents[0].len(), 22);
+    // the header is not part of PlainAnpass
+    assert_eq!(Anpass::from_plain(&plain), anpass.with_header(""));
+}
+
+#[test]
+fn test_builder() {
+    let anpass = Anpass::load_file("testfiles/anpass.in");
+    let built = Anpass::builder()
+        .disps(anpass.disps.clone())
+        .energies(anpass.energies.clone())
+        .exponents(anpass.exponents.clone())
+        .build()
+        .unwrap();
+    assert_eq!(built, anpass.clone().with_header(""));
+    assert_eq!(built.fit(), anpass.fit());
+
+    // energies may be omitted for a template
+    let template = Anpass::builder()
+        .disps(anpass.disps.clone())
+        .exponents(anpass.exponents.clone())
+        .build()
+        .unwrap();
+    assert!(template.energies.is_empty());
+
+    let err = |b: AnpassBuilder| b.build().unwrap_err().to_string();
+    let base = Anpass::builder()
+        .disps(anpass.disps.clone())
+        .energies(anpass.energies.clone());
+    assert_eq!(err(base.clone()), "`exponents` is required");
+    assert_eq!(
+        err(Anpass::builder().exponents(anpass.exponents.clone())),
+        "`disps` is required"
+    );
+    assert_eq!(
+        err(base.clone().exponents(anpass.exponents.rows(0, 2).into())),
+        "exponents have 2 rows, expected 3 to match the columns of disps"
+    );
+    assert_eq!(
+        err(base
+            .clone()
+            .exponents(anpass.exponents.clone())
+            .energies(Dvec::zeros(3))),
+        "3 energies given for 69 displacements"
+    );
+    assert_eq!(
+        err(base
+            .clone()
+            .exponents(anpass.exponents.clone())
+            .weights(Dvec::zeros(68))),
+        "68 weights given for 69 displacements"
+    );
+    assert_eq!(
+        err(base.exponents(anpass.exponents.clone()).bias(Bias {
+            disp: Dvec::zeros(2),
+            energy: 0.0,
+        })),
+        "bias has 2 coordinates, expected 3"
+    );
+
+    let multi = Anpass::load(h2o_multi().as_bytes());
+    let surfaces = multi.surfaces.clone().unwrap();
+    let built = Anpass::builder()
+        .disps(multi.disps.clone())
+        .energies(multi.energies.clone())
+        .exponents(multi.exponents.clone())
+        .surfaces(surfaces.clone())
+        .header(multi.header())
+        .build()
+        .unwrap();
+    assert_eq!(built, multi);
+    assert_eq!(
+        err(Anpass::builder()
+            .disps(multi.disps.clone())
+            .energies(multi.energies.add_scalar(1.0))
+            .exponents(multi.exponents.clone())
+            .surfaces(surfaces)),
+        "the first column of surfaces does not match energies"
+    );
+}
+
+/// a grid of displacements with energies from the saddle x² - y² + 0.01x
+fn saddle() -> Anpass {
+    let mut disps = Vec::new();
+    let mut energies = Vec::new();
+    for i in -3..=3 {
+        for j in -3..=3 {
+            let (x, y) = (0.02 * i as f64, 0.02 * j as f64);
+            disps.extend([x, y]);
+            energies.push(x * x - y * y + 0.01 * x);
+        }
+    }
+    fixture(
+        Dmat::from_row_slice(energies.len(), 2, &disps),
+        Dvec::from(energies),
+        na::DMatrix::from_row_slice(
+            2,
+            6,
+            &[0, 1, 0, 2, 1, 0, 0, 0, 1, 0, 1, 2],
+        ),
+    )
+}
+
+#[test]
+fn test_run_guarded() {
+    let anpass = saddle();
+    let (_, bias, _, kind) = anpass.run_guarded(false).unwrap();
+    assert_eq!(kind, StatKind::Stat);
+    assert_abs_diff_eq!(bias.disp, na::dvector![-0.005, 0.0], epsilon = 1e-7);
+    assert_eq!(
+        anpass.run_guarded(true).unwrap_err(),
+        AnpassError::WrongStationaryType {
+            found: StatKind::Stat
+        }
+    );
+
+    let anpass = Anpass::load_file("testfiles/c3h2.in");
+    assert!(anpass.run_guarded(true).is_ok());
+}
+
+#[test]
+fn test_frequencies() {
+    let anpass = Anpass::load_file("testfiles/c3h2.in");
+    let n = anpass.n_variables();
+    let got = anpass.frequencies(&Dvec::repeat(n, 1.0)).unwrap();
+    assert_eq!(got.len(), n);
+    assert!(got.iter().all(|f| f.is_finite()));
+    assert!(got.as_slice().windows(2).all(|w| w[0] <= w[1]));
+
+    // a single harmonic oscillator with k = 1 hartree/Å² and m = 1 amu
+    let anpass = fixture(
+        Dmat::from_row_slice(3, 1, &[-0.01, 0.0, 0.01]),
+        na::dvector![0.5e-4, 0.0, 0.5e-4],
+        na::DMatrix::from_row_slice(1, 1, &[2]),
+    );
+    let got = anpass.frequencies(&na::dvector![1.0]).unwrap();
+    assert_abs_diff_eq!(
+        got[0],
+        1302.7914 * 4.359813653_f64.sqrt(),
+        epsilon = 1e-6
+    );
+
+    assert!(anpass.frequencies(&na::dvector![1.0, 2.0]).is_err());
+}
+
+#[test]
+fn test_energy_stats() {
+    let anpass = Anpass::load_file("testfiles/c3h2.in");
+    let got = anpass.energy_stats().unwrap();
+    let mut e: Vec<f64> = anpass.energies.iter().cloned().collect();
+    e.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let n = e.len() as f64;
+    let mean = e.iter().sum::<f64>() / n;
+    let var = e.iter().map(|x| (x - mean) * (x - mean)).sum::<f64>() / n;
+    let median = if e.len().is_multiple_of(2) {
+        (e[e.len() / 2 - 1] + e[e.len() / 2]) / 2.0
+    } else {
+        e[e.len() / 2]
+    };
+    assert_eq!(got.min, e[0]);
+    assert_eq!(got.max, *e.last().unwrap());
+    assert_abs_diff_eq!(got.mean, mean, epsilon = 1e-15);
+    assert_eq!(got.median, median);
+    assert_abs_diff_eq!(got.std_dev, var.sqrt(), epsilon = 1e-15);
+
+    let empty = Anpass {
+        energies: Dvec::zeros(0),
+        ..anpass
+    };
+    assert!(empty.energy_stats().is_none());
+}
+
+#[test]
+fn test_write9903_checksum() {
+    let anpass = Anpass::load_file("testfiles/h2o.in");
+    let (coeffs, _) = anpass.fit();
+    let mut fcs = anpass.make9903(&coeffs);
+    let mut buf = Vec::new();
+    let got = anpass.write9903_checksum(&mut buf, &fcs);
+    assert_eq!(got, crate::fc::checksum(&buf));
+    let mut buf2 = Vec::new();
+    assert_eq!(anpass.write9903_checksum(&mut buf2, &fcs), got);
+    assert_eq!(buf, buf2);
+
+    fcs[5].value += 1e-9;
+    let changed = anpass.write9903_checksum(&mut std::io::sink(), &fcs);
+    assert_ne!(changed, got);
+}
+
+#[test]
+fn test_fit_ordered() {
+    // columns: y², 1, xy, x, y, x²
+    let anpass = Anpass {
+        exponents: na::DMatrix::from_row_slice(
+            2,
+            6,
+            &[0, 0, 1, 1, 0, 2, 2, 0, 1, 0, 1, 0],
+        ),
+        ..noisy_quadratic()
+    };
+    let (got, x) = anpass.fit_ordered();
+    let (coeffs, want_x) = anpass.fit();
+    assert_eq!(x, want_x);
+    let exps: Vec<_> = got.iter().map(|(e, _)| e.clone()).collect();
+    assert_eq!(
+        exps,
+        vec![
+            vec![0, 0],
+            vec![0, 1],
+            vec![1, 0],
+            vec![0, 2],
+            vec![1, 1],
+            vec![2, 0],
+        ]
+    );
+    assert_eq!(got[0].1, coeffs[1]);
+    assert_eq!(got[1].1, coeffs[4]);
+    assert_eq!(got[2].1, coeffs[3]);
+    assert_eq!(got[3].1, coeffs[0]);
+    assert_eq!(got[4].1, coeffs[2]);
+    assert_eq!(got[5].1, coeffs[5]);
+}
+
+#[test]
+fn test_harmonic_shift() {
+    let anpass = Anpass::load_file("testfiles/c3h2.in");
+    let (coeffs, _) = anpass.fit();
+    let got = anpass.harmonic_shift(&coeffs).unwrap();
+    // newton takes half of the harmonic step on its first iteration
+    let x = Dvec::zeros(anpass.n_variables());
+    let g = anpass.grad(&x, &coeffs);
+    let h = anpass.hess(&x, &coeffs);
+    let first = -0.5 * h.try_inverse().unwrap() * g;
+    assert_abs_diff_eq!(0.5 * got, first, epsilon = 1e-12);
+
+    let anpass = saddle();
+    let (coeffs, _) = anpass.fit();
+    assert!(anpass.harmonic_shift(&coeffs).is_err());
+}
+
+#[test]
+fn test_compare_predictions() {
+    let anpass = noisy_quadratic();
+    let (a, _) = anpass.fit();
+    assert_eq!(anpass.compare_predictions(&a, &a), (0.0, 0));
+
+    // perturbing the x coefficient changes the prediction most at the points
+    // with the largest |x|, the first of which is (-0.06, -0.06)
+    let mut b = a.clone();
+    b[1] += 1e-3;
+    let (got, i) = anpass.compare_predictions(&a, &b);
+    assert_eq!(i, 0);
+    assert_abs_diff_eq!(got, 6e-5, epsilon = 1e-15);
+
+    // with the y² and xy coefficients also perturbed, the three terms add at
+    // (-0.06, 0.06)
+    b[2] -= 1e-2;
+    b[3] += 1e-1;
+    let (got, i) = anpass.compare_predictions(&a, &b);
+    assert_eq!(i, 6);
+    assert_abs_diff_eq!(got, 4.56e-4, epsilon = 1e-15);
+}
+
+#[test]
+fn test_run_auto() {
+    // exactly quadratic about the origin, so no bias is needed
+    let mut anpass = noisy_quadratic();
+    anpass.energies = anpass
+        .disps
+        .row_iter()
+        .map(|r| 1.0 + 2.0 * r[0] * r[0] + 3.0 * r[1] * r[1] + r[0] * r[1])
+        .collect::<Vec<_>>()
+        .into();
+    anpass.exponents =
+        na::DMatrix::from_row_slice(2, 4, &[0, 2, 0, 1, 0, 0, 2, 1]);
+    let (coeffs, _) = anpass.fit();
+    let (fcs, bias) = anpass.run_auto().unwrap();
+    assert!(bias.is_none());
+    assert_eq!(fcs, anpass.make9903(&coeffs));
+
+    // the c3h2 data are off-center, so this should match run
+    let anpass = Anpass::load_file("testfiles/c3h2.in");
+    let (fcs, bias) = anpass.run_auto().unwrap();
+    let (want_fcs, want_bias, _, _) = anpass.run().unwrap();
+    assert_eq!(fcs, want_fcs);
+    assert_eq!(bias, Some(want_bias));
+}
+
+#[test]
+fn test_strongest_coupling() {
+    let anpass = Anpass::load_file("testfiles/c3h2.in");
+    let hess = Dmat::from_row_slice(
+        3,
+        3,
+        &[5.0, 0.1, -0.2, 0.1, 4.0, -0.7, -0.2, -0.7, 3.0],
+    );
+    assert_eq!(anpass.strongest_coupling(&hess), (1, 2, -0.7));
+    assert!(anpass.strongest_coupling(&Dmat::zeros(1, 1)).2.is_nan());
+}
+
+#[test]
+fn test_from_str() {
+    let input = std::fs::read_to_string("testfiles/c3h2.in").unwrap();
+    let got: Anpass = input.parse().unwrap();
+    assert_eq!(got, Anpass::load_file("testfiles/c3h2.in"));
+
+    // round trip through Display
+    for f in ["testfiles/anpass.in", "testfiles/anpass_weights.in"] {
+        let anpass = Anpass::load_file(f);
+        let got: Anpass = anpass.to_string().parse().unwrap();
+        assert_eq!(got, anpass);
+    }
+
+    assert_eq!(
+        "".parse::<Anpass>().unwrap_err(),
+        AnpassError::MissingFormatLine
+    );
+}
+
+#[test]
+fn test_load_bufread() {
+    let input = std::fs::read_to_string("testfiles/h2o.in").unwrap();
+    let want = Anpass::load_file("testfiles/h2o.in");
+    assert_eq!(Anpass::load(std::io::Cursor::new(input.clone())), want);
+    assert_eq!(Anpass::load(input.as_bytes()), want);
+    let f = std::fs::File::open("testfiles/h2o.in").unwrap();
+    assert_eq!(Anpass::load(BufReader::new(f)), want);
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn test_load_gzip() {
+    use std::io::Write;
+    let input = std::fs::read("testfiles/c3h2.in").unwrap();
+    let path = std::env::temp_dir().join("rust-anpass-c3h2.in.gz");
+    let f = std::fs::File::create(&path).unwrap();
+    let mut enc =
+        flate2::write::GzEncoder::new(f, flate2::Compression::default());
+    enc.write_all(&input).unwrap();
+    enc.finish().unwrap();
+    let got = Anpass::load_file(path.to_str().unwrap());
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(got, Anpass::load_file("testfiles/c3h2.in"));
+}
+
+#[test]
+fn test_fortran_d_exponents() {
+    let input = std::fs::read_to_string("testfiles/anpass.in").unwrap();
+    let want = Anpass::load(input.as_bytes());
+    let mixed = input
+        .replacen("0.000128387078", "1.28387078D-04", 1)
+        .replacen("0.000027809414", "2.7809414d-05", 1)
+        .replacen("0.000035977201", "3.5977201E-05", 1)
+        .replacen("-0.01000000", "-1.0D-02", 1)
+        .replace(
+            "!STATIONARY POINT",
+            "STATIONARY POINT\n 1.0D-03 -2.0d-03 0.0 -7.5D-06",
+        );
+    let got = Anpass::load(mixed.as_bytes());
+    assert_eq!(got.energies.len(), want.energies.len());
+    assert_eq!(got.disps, want.disps);
+    assert_abs_diff_eq!(got.energies, want.energies, epsilon = 1e-15);
+    let bias = got.bias.unwrap();
+    assert_eq!(bias.disp, na::dvector![1e-3, -2e-3, 0.0]);
+    assert_eq!(bias.energy, -7.5e-6);
+}
+
+#[test]
+fn test_comment_lines() {
+    let input = std::fs::read_to_string("testfiles/anpass.in").unwrap();
+    let want = Anpass::load(input.as_bytes());
+    let mut commented = String::new();
+    for (i, line) in input.lines().enumerate() {
+        if i % 5 == 0 {
+            commented.push_str("# CCSD(T)-F12/cc-pVTZ-F12\n");
+        }
+        if i % 7 == 0 {
+            commented.push_str("   ! 1.0 2.0 3.0 4.0\n");
+        }
+        commented.push_str(line);
+        commented.push('\n');
+    }
+    // comments before the format line are kept in the header
+    let got = Anpass::load(commented.as_bytes());
+    assert!(got.header().starts_with("# CCSD(T)"));
+    assert_eq!(got.with_header(want.header()), want);
+}
+
+#[test]
+fn test_from_csv() {
+    let csv = "0.0, 0.0, 1.5\n0.1,-0.1,1.75\n\n-0.2,0.3,2.0\n";
+    let got = Anpass::from_csv(csv.as_bytes(), true).unwrap();
+    assert_eq!(
+        got.disps,
+        Dmat::from_row_slice(3, 2, &[0.0, 0.0, 0.1, -0.1, -0.2, 0.3])
+    );
+    assert_eq!(got.energies, na::dvector![1.5, 1.75, 2.0]);
+    assert_eq!(got.n_variables(), 2);
+    assert_eq!(got.exponents.ncols(), 0);
+
+    let got = Anpass::from_csv(csv.as_bytes(), false).unwrap();
+    assert_eq!(got.disps.shape(), (3, 3));
+    assert!(got.energies.is_empty());
+
+    // the full anpass input as csv
+    let want = Anpass::load_file("testfiles/h2o.in");
+    let mut csv = String::new();
+    for (row, e) in want.disps.row_iter().zip(want.energies.iter()) {
+        for d in row.iter() {
+            csv.push_str(&format!("{d},"));
+        }
+        csv.push_str(&format!("{e}\n"));
+    }
+    let got = Anpass::from_csv(csv.as_bytes(), true).unwrap();
+    assert_eq!(got.disps, want.disps);
+    assert_eq!(got.energies, want.energies);
+
+    let ragged = "0.0,0.0,1.5\n0.1,1.75\n";
+    assert_eq!(
+        Anpass::from_csv(ragged.as_bytes(), true).unwrap_err(),
+        AnpassError::RowWidth {
+            line: 2,
+            expected: 3,
+            found: 2
+        }
+    );
+    assert_eq!(
+        Anpass::from_csv("x,y,E\n".as_bytes(), true).unwrap_err(),
+        AnpassError::InvalidField {
+            line: 1,
+            field: "x".to_owned()
+        }
+    );
+}
+
+/// the h2o input with two more surfaces: a constant shift and a scaled copy
+fn h2o_multi() -> String {
+    let input = std::fs::read_to_string("testfiles/h2o.in").unwrap();
+    let mut multi = String::new();
+    let mut in_disps = false;
+    for line in input.lines() {
+        if line.contains("UNKNOWNS") {
+            in_disps = false;
+        }
+        if in_disps {
+            let e: f64 =
+                line.split_whitespace().last().unwrap().parse().unwrap();
+            multi.push_str(&format!(
+                "{line}{:20.12}{:20.12}\n",
+                e + 0.5,
+                2.0 * e
+            ));
+        } else if line.contains("F12.8") {
+            multi.push_str("(3F12.8,3f20.12)\n");
+            in_disps = true;
+        } else {
+            multi.push_str(line);
+            multi.push('\n');
+        }
+    }
+    multi
+}
+
+#[test]
+fn test_fit_multi() {
+    let single = Anpass::load_file("testfiles/h2o.in");
+    let multi = h2o_multi();
+    let anpass = Anpass::load(multi.as_bytes());
+    assert_eq!(anpass.energies, single.energies);
+    let surfaces = anpass.surfaces.as_ref().unwrap();
+    assert_eq!(surfaces.shape(), (anpass.disps.nrows(), 3));
+
+    let (coeffs, x) = anpass.fit_multi();
+    let (want, want_x) = single.fit();
+    assert_eq!(x, want_x);
+    assert_eq!(coeffs.shape(), (want.len(), 3));
+    assert_abs_diff_eq!(coeffs.column(0).into_owned(), want, epsilon = 1e-10);
+    let mut shifted = want.clone();
+    shifted[0] += 0.5;
+    // the large constant offset costs a few digits in the solution
+    assert_abs_diff_eq!(coeffs.column(1).into_owned(), shifted, epsilon = 1e-5);
+    assert_abs_diff_eq!(
+        coeffs.column(2).into_owned(),
+        2.0 * &want,
+        epsilon = 1e-10
+    );
+
+    // a single surface gives a single column
+    let (coeffs, _) = single.fit_multi();
+    assert_abs_diff_eq!(coeffs.column(0).into_owned(), want, epsilon = 1e-10);
+
+    // round trip through Display
+    let round: Anpass = anpass.to_string().parse().unwrap();
+    assert_eq!(round, anpass);
+}
+
+#[test]
+fn test_bias_multi() {
+    let anpass = Anpass::load(h2o_multi().as_bytes());
+    let bias = Bias {
+        disp: Dvec::from(vec![0.001, -0.002, 0.003]),
+        energy: -76.0,
+    };
+    let biased = anpass.bias(&bias);
+    let surfaces = biased.surfaces.as_ref().unwrap();
+    assert_eq!(surfaces.column(0), biased.energies.column(0));
+    assert_eq!(
+        surfaces,
+        &anpass.surfaces.as_ref().unwrap().add_scalar(76.0)
+    );
+
+    let mut biased_mut = anpass.clone();
+    biased_mut.bias_mut(&bias);
+    assert_eq!(biased_mut, biased);
+
+    let (coeffs, _) = biased.fit_multi();
+    let (want, _) = biased.fit();
+    assert_abs_diff_eq!(coeffs.column(0).into_owned(), want, epsilon = 1e-10);
+
+    // the biased energies, not the original ones, are written and reloaded
+    let round = Anpass::load(biased.to_string().as_bytes());
+    assert_abs_diff_eq!(round.energies, biased.energies, epsilon = 1e-10);
+    assert_abs_diff_eq!(
+        round.surfaces.unwrap(),
+        biased.surfaces.clone().unwrap(),
+        epsilon = 1e-10
+    );
+
+    let unbiased = biased.unbias(&bias);
+    assert_abs_diff_eq!(
+        unbiased.surfaces.unwrap(),
+        anpass.surfaces.unwrap(),
+        epsilon = 1e-10
+    );
+}
+
+#[test]
+fn test_header() {
+    let input = std::fs::read_to_string("testfiles/anpass.in").unwrap();
+    let anpass = Anpass::load(input.as_bytes());
+    let preamble = input.split("(3F12.8,f20.12)").next().unwrap();
+    assert_eq!(anpass.header(), preamble);
+    assert!(anpass.to_string().starts_with(preamble));
+    assert!(anpass.to_string().contains("H2O 2A1 F12-TZ"));
+
+    let custom = "TITLE\n custom\nINDEPENDENT VARIABLES\n   3\nDATA POINTS";
+    let anpass = anpass.with_header(custom);
+    assert_eq!(anpass.header(), format!("{custom}\n"));
+    let round: Anpass = anpass.to_string().parse().unwrap();
+    assert_eq!(round.header(), anpass.header());
+    assert_eq!(round, anpass);
+
+    let anpass = anpass.with_header("");
+    assert!(anpass
+        .to_string()
+        .starts_with("!INPUT\nTITLE\nfrom rust-anpass"));
+}
+
+#[test]
+fn test_header_counts() {
+    let anpass = Anpass::load_file("testfiles/anpass.in");
+    let (train, test) = anpass.train_test_split(0.25, 1);
+    let n = train.disps.nrows();
+    assert!(n < 69);
+    let s = train.to_string();
+    assert!(s.contains(&format!(
+        "INDEPENDENT VARIABLES\n   3\nDATA POINTS\n{n:4}   -2\n"
+    )));
+    assert!(s.contains(" H2O 2A1 F12-TZ\nPRINT\n   99\n"));
+    let round: Anpass = s.parse().unwrap();
+    assert_eq!(round.disps.nrows(), n);
+    assert!(test
+        .to_string()
+        .contains(&format!("DATA POINTS\n{:4}   -2\n", 69 - n)));
+
+    // counts in a replaced header are corrected too
+    let custom = "TITLE\nINDEPENDENT VARIABLES\n  12\nDATA POINTS\n   1\n";
+    let s = anpass.with_header(custom).to_string();
+    assert!(s.starts_with(
+        "TITLE\nINDEPENDENT VARIABLES\n   3\nDATA POINTS\n  69\n"
+    ));
+}
+
+#[test]
+fn test_set_energies_from() {
+    let want = Anpass::load_file("testfiles/c3h2.in");
+    let mut template = want.clone();
+    template.energies = Dvec::zeros(0);
+    let mut energies = String::new();
+    for e in want.energies.iter() {
+        energies.push_str(&format!("{e:20.12}\n"));
+    }
+    energies.push('\n');
+    template.set_energies_from(energies.as_bytes()).unwrap();
+    assert_eq!(template, want);
+
+    let mut template = want.clone();
+    template.energies = Dvec::zeros(0);
+    let short = energies.lines().skip(1).collect::<Vec<_>>().join("\n");
+    assert_eq!(
+        template.set_energies_from(short.as_bytes()).unwrap_err(),
+        AnpassError::Other(format!(
+            "read {} energies for {} displacements",
+            want.energies.len() - 1,
+            want.energies.len()
+        ))
+    );
+    assert!(template.energies.is_empty());
+    assert_eq!(
+        template
+            .set_energies_from("1.0\nnan?\n".as_bytes())
+            .unwrap_err(),
+        AnpassError::InvalidField {
+            line: 2,
+            field: "nan?".to_owned()
+        }
+    );
+}
+
+#[test]
+fn test_format_line_variants() {
+    let input = std::fs::read_to_string("testfiles/anpass.in").unwrap();
+    let want = Anpass::load(input.as_bytes());
+    for fmt in [
+        "(3F12.8,f20.12)",
+        "(3f12.8,F20.12)",
+        "  (3F12.8,f20.12)  ",
+        "( 3F12.8 , F20.12 )",
+        "(3F12.8, f20.12)",
+        "(\t3F12.8 ,F20.12)",
+        "(3 F12.8,1 F20.12)",
+    ] {
+        let got =
+            Anpass::load(input.replace("(3F12.8,f20.12)", fmt).as_bytes());
+        assert_eq!(got.disps, want.disps, "{fmt}");
+        assert_eq!(got.energies, want.energies, "{fmt}");
+    }
+    let bad = input.replace("(3F12.8,f20.12)", "(3F12.8;f20.12)");
+    assert_eq!(
+        Anpass::parse(bad.as_bytes()).unwrap_err(),
+        AnpassError::MissingFormatLine
+    );
+}
+
+#[test]
+fn test_write_input() {
+    let anpass = Anpass::load_file("testfiles/anpass.in");
+    let mut buf = Vec::new();
+    anpass.write_input(&mut buf, 12, 8, 12);
+    assert_eq!(String::from_utf8(buf).unwrap(), anpass.to_string());
+
+    let mut buf = Vec::new();
+    anpass.write_input(&mut buf, 16, 10, 14);
+    let got = String::from_utf8(buf).unwrap();
+    assert!(got.contains("(3F16.10,f22.14)\n"));
+    assert!(got.contains(
+        "   -0.0050000000   -0.0050000000   -0.0100000000      \
+         0.00012838707800\n"
+    ));
+    assert_eq!(got.parse::<Anpass>().unwrap(), anpass);
+
+    // a template without energies
+    let mut template = anpass.clone();
+    template.energies = Dvec::zeros(0);
+    let mut buf = Vec::new();
+    template.write_input(&mut buf, 12, 8, 12);
+    let got = String::from_utf8(buf).unwrap();
+    assert!(got.contains("\n -0.00500000 -0.00500000 -0.01000000\n"));
+    assert_eq!(got.parse::<Anpass>().unwrap(), template);
+}
+
+#[test]
+fn test_fit_weighted() {
+    let anpass = Anpass::load_file("testfiles/c3h2.in");
+    let n = anpass.disps.nrows();
+    let (want, want_x) = anpass.fit();
+    let (got, x) = anpass.fit_weighted(&Dvec::repeat(n, 1.0));
+    assert_eq!(got, want);
+    assert_eq!(x, want_x);
+
+    // matches the weights read from the input
+    let weighted = Anpass::load_file("testfiles/anpass_weights.in");
+    let plain = Anpass::load_file("testfiles/anpass.in");
+    let weights = weighted.weights.clone().unwrap();
+    assert_eq!(plain.fit_weighted(&weights), weighted.fit());
+
+    // upweighting the points near the minimum tightens the fit there
+    let (plain_coeffs, f) = plain.fit();
+    let near: Vec<_> = (0..plain.disps.nrows())
+        .filter(|&i| plain.disps.row(i).norm() < 0.011)
+        .collect();
+    let mut weights = Dvec::repeat(plain.disps.nrows(), 1.0);
+    for &i in &near {
+        weights[i] = 100.0;
+    }
+    let (coeffs, _) = plain.fit_weighted(&weights);
+    let sse = |c: &Dvec| {
+        let r = &f * c - &plain.energies;
+        near.iter().map(|&i| r[i] * r[i]).sum::<f64>()
+    };
+    assert!(sse(&coeffs) < sse(&plain_coeffs));
+}
+
+#[test]
+#[should_panic(expected = "number of weights does not match")]
+fn test_fit_weighted_mismatch() {
+    let anpass = Anpass::load_file("testfiles/anpass.in");
+    anpass.fit_weighted(&Dvec::repeat(3, 1.0));
+}
+
+#[test]
+fn test_fit_ridge() {
+    let anpass = Anpass::load_file("testfiles/anpass.in");
+    assert_eq!(anpass.fit_ridge(0.0), anpass.fit());
+
+    // y is almost exactly x, so the x and y columns are nearly collinear
+    let mut state = 7;
+    let mut disps = Vec::new();
+    let mut energies = Vec::new();
+    for i in -10..=10 {
+        let x = 0.01 * i as f64;
+        let y = x + 1e-7 * lcg(&mut state);
+        disps.extend([x, y]);
+        energies.push(1.0 + x + y + 1e-5 * lcg(&mut state));
+    }
+    let anpass = fixture(
+        Dmat::from_row_slice(energies.len(), 2, &disps),
+        Dvec::from(energies),
+        na::DMatrix::from_row_slice(2, 3, &[0, 1, 0, 0, 0, 1]),
+    );
+    let (ols, _) = anpass.fit();
+    let (ridge, _) = anpass.fit_ridge(1e-6);
+    assert!(ols.norm() > 10.0);
+    assert!(ridge.norm() < 2.0);
+    // the regularized fit splits the slope evenly between x and y
+    assert_abs_diff_eq!(ridge[1], 1.0, epsilon = 1e-2);
+    assert_abs_diff_eq!(ridge[2], 1.0, epsilon = 1e-2);
+
+    // the weights are ignored, so without regularization this is the
+    // unweighted fit
+    let weighted = Anpass::load_file("testfiles/anpass_weights.in");
+    let unweighted = Anpass {
+        weights: None,
+        ..weighted.clone()
+    };
+    assert_eq!(weighted.fit_ridge(0.0), unweighted.fit());
+    assert_ne!(weighted.fit_ridge(0.0), weighted.fit());
+}
+
+#[test]
+#[should_panic(expected = "ridge parameter must not be negative")]
+fn test_fit_ridge_negative() {
+    let anpass = Anpass::load_file("testfiles/anpass.in");
+    anpass.fit_ridge(-1e-3);
+}
+
+#[test]
+fn test_fit_svd() {
+    let anpass = Anpass::load_file("testfiles/anpass.in");
+    let (got, x) = anpass.fit_svd(1e-14);
+    let (want, want_x) = anpass.fit();
+    assert_eq!(x, want_x);
+    assert_abs_diff_eq!(got, want, epsilon = 1e-8);
+
+    // y is exactly x, so the x and y columns are identical and XᵀX is
+    // singular. the minimum-norm solution splits the slope evenly
+    let mut disps = Vec::new();
+    let mut energies = Vec::new();
+    for i in -10..=10 {
+        let x = 0.01 * i as f64;
+        disps.extend([x, x]);
+        energies.push(1.0 + 2.0 * x);
+    }
+    let anpass = fixture(
+        Dmat::from_row_slice(energies.len(), 2, &disps),
+        Dvec::from(energies),
+        na::DMatrix::from_row_slice(2, 3, &[0, 1, 0, 0, 0, 1]),
+    );
+    let (got, _) = anpass.fit_svd(1e-12);
+    assert_abs_diff_eq!(got, na::dvector![1.0, 1.0, 1.0], epsilon = 1e-12);
+
+    // with y identically zero, XᵀX has a zero row and column, so both the
+    // Cholesky and LU decompositions in fit fail, and fit falls back on the
+    // SVD instead of panicking
+    let anpass = Anpass {
+        disps: Dmat::from_fn(21, 2, |i, j| {
+            if j == 0 {
+                0.01 * (i as f64 - 10.0)
+            } else {
+                0.0
+            }
+        }),
+        ..anpass
+    };
+    let (got, _) = anpass.fit();
+    assert_abs_diff_eq!(got, na::dvector![1.0, 2.0, 0.0], epsilon = 1e-12);
+}
+
+#[test]
+fn test_fit_qr() {
+    let anpass = noisy_quadratic();
+    let (got, x) = anpass.fit_qr();
+    let (want, want_x) = anpass.fit();
+    assert_eq!(x, want_x);
+    assert_abs_diff_eq!(got, want, epsilon = 1e-10);
+
+    let anpass = Anpass::load_file("testfiles/h2o.in");
+    let (got, _) = anpass.fit_qr();
+    let (want, f) = anpass.fit();
+    assert_relative_eq!(got, want, epsilon = 1e-8, max_relative = 1e-6);
+    // QR should reproduce the data at least as well as the normal equations
+    assert!(
+        anpass.sum_squared_residuals(&got, &f)
+            <= anpass.sum_squared_residuals(&want, &f) * (1.0 + 1e-8)
+    );
+}
+
+#[test]
+fn test_condition_number() {
+    let anpass = Anpass::load_file("testfiles/anpass.in");
+    let got = anpass.condition_number();
+    let (_, x) = anpass.fit();
+    let s = x.svd(false, false).singular_values;
+    // the eigenvalues of XᵀX are the squares of the singular values of X
+    let want = (s.max() / s.min()).powi(2);
+    assert_relative_eq!(got, want, max_relative = 1e-4);
+    // fit_auto_ridge only regularizes when this exceeds its target
+    assert!(got > 1e6);
+    assert_eq!(anpass.fit_auto_ridge(got * 2.0).2, 0.0);
+
+    let mut singular = noisy_quadratic();
+    singular.disps.column_mut(1).fill(0.0);
+    assert_eq!(singular.condition_number(), f64::INFINITY);
+}
+
+#[test]
+fn test_residuals() {
+    let anpass = Anpass::load_file("testfiles/c3h2.in");
+    let (coeffs, f) = anpass.fit();
+    let got = anpass.point_residuals(&coeffs);
+    assert_eq!(got.len(), anpass.disps.nrows());
+    assert_abs_diff_eq!(
+        got.norm_squared(),
+        anpass.sum_squared_residuals(&coeffs, &f),
+        epsilon = 1e-20
+    );
+    let (i, max) = anpass.max_residual(&coeffs, &f);
+    assert_eq!(got[i], max);
+    // the old name of sum_squared_residuals still works
+    #[allow(deprecated)]
+    let old = anpass.residuals(&coeffs, &f);
+    assert_eq!(old, anpass.sum_squared_residuals(&coeffs, &f));
+}
+
+#[test]
+#[should_panic(expected = "cannot compute residuals without energies")]
+fn test_residuals_no_energies() {
+    let mut anpass = Anpass::load_file("testfiles/anpass.in");
+    let (coeffs, _) = anpass.fit();
+    anpass.energies = Dvec::zeros(0);
+    anpass.point_residuals(&coeffs);
+}
+
+#[test]
+fn test_fit_statistics() {
+    let anpass = noisy_quadratic();
+    let (coeffs, _) = anpass.fit();
+    let got = anpass.fit_statistics(&coeffs);
+    let resid = anpass.point_residuals(&coeffs);
+    assert_eq!(got.n_points, 49);
+    assert_eq!(got.max_abs_residual, resid.amax());
+    assert_abs_diff_eq!(
+        got.rmsd,
+        (resid.norm_squared() / 49.0).sqrt(),
+        epsilon = 1e-16
+    );
+    // the noise is uniform in ±5e-5
+    assert!(got.rmsd < 5e-5 && got.rmsd > 1e-5);
+    assert!(got.r_squared > 0.999 && got.r_squared < 1.0);
+
+    // predicting the mean everywhere gives R² = 0
+    let mut mean = Dvec::zeros(coeffs.len());
+    mean[0] = anpass.energies.mean();
+    assert_abs_diff_eq!(
+        anpass.fit_statistics(&mean).r_squared,
+        0.0,
+        epsilon = 1e-12
+    );
+}
+
+#[test]
+fn test_coefficient_covariance_weighted() {
+    // with the variance of each energy σ²/wᵢ, the covariance of the fitted
+    // coefficients is σ² Σᵢ sᵢsᵢᵀ/wᵢ, where sᵢ is the sensitivity of the
+    // coefficients to energy i
+    let anpass = Anpass::load_file("testfiles/anpass_weights.in");
+    let weights = anpass.weights.clone().unwrap();
+    let (coeffs, x) = anpass.fit();
+    let (n, p) = x.shape();
+    let resid = anpass.point_residuals(&coeffs);
+    let sigma2 = resid.component_mul(&resid).dot(&weights) / (n - p) as f64;
+    let mut want = Dmat::zeros(p, p);
+    for (i, w) in weights.iter().enumerate() {
+        let s = fd_sensitivity(&anpass, i);
+        want += &s * s.transpose() / *w;
+    }
+    want *= sigma2;
+    let got = anpass.coefficient_covariance(&coeffs);
+    assert_relative_eq!(got, want, epsilon = 1e-12, max_relative = 1e-5);
+}
+
+#[test]
+fn test_coefficient_covariance() {
+    let anpass = noisy_quadratic();
+    let (coeffs, x) = anpass.fit();
+    let got = anpass.coefficient_covariance(&coeffs);
+    let (n, p) = x.shape();
+    let sigma2 =
+        anpass.point_residuals(&coeffs).norm_squared() / (n - p) as f64;
+    let want = sigma2 * (x.transpose() * &x).try_inverse().unwrap();
+    assert_relative_eq!(got, want, max_relative = 1e-10);
+    assert_relative_eq!(got, got.transpose(), max_relative = 1e-12);
+
+    let errs = anpass.coefficient_std_errors(&coeffs);
+    assert_eq!(errs, got.diagonal().map(f64::sqrt));
+    // the noise has standard deviation 1e-4/√12, and the constant term is
+    // determined about as well as the mean of 49 points
+    assert_relative_eq!(errs[0], 1e-4 / 12f64.sqrt() / 7.0, max_relative = 0.5);
+    // the xy term is not in the model, so it is poorly determined, as
+    // reported by ill_determined_terms
+    assert!(errs[3] > 0.5 * coeffs[3].abs());
+    assert_eq!(anpass.ill_determined_terms(&coeffs, &x, 0.5), vec![3]);
+
+    // without degrees of freedom the covariance is undefined
+    for n in [p - 1, p] {
+        let few = Anpass {
+            disps: anpass.disps.rows(0, n).into_owned(),
+            energies: anpass.energies.rows(0, n).into_owned(),
+            ..anpass.clone()
+        };
+        let cov = few.coefficient_covariance(&coeffs);
+        assert_eq!(cov.shape(), (p, p));
+        assert!(cov.iter().all(|c| c.is_nan()));
+        assert!(few
+            .coefficient_std_errors(&coeffs)
+            .iter()
+            .all(|e| e.is_nan()));
+    }
+}
+
+#[test]
+fn test_with_design() {
+    let anpass = Anpass::load_file("testfiles/c3h2.in");
+    let x = anpass.build_design_matrix();
+    let (want, want_x) = anpass.fit();
+    assert_eq!(x, want_x);
+    let (got, got_x) = anpass.fit_with_design(Some(x.clone()));
+    assert_eq!(got, want);
+    assert_eq!(got_x, x);
+    assert_eq!(
+        anpass.point_residuals_with_design(&got, Some(&x)),
+        anpass.point_residuals(&want)
+    );
+    assert_eq!(
+        anpass.fit_statistics_with_design(&got, Some(&x)),
+        anpass.fit_statistics(&want)
+    );
+
+    let weighted = Anpass::load_file("testfiles/anpass_weights.in");
+    let x = weighted.build_design_matrix();
+    assert_eq!(weighted.fit_with_design(Some(x)), weighted.fit());
+}
+
+/// a synthetic surface with `n` random points in `nvbl` variables and every
+/// monomial through total degree `degree`
+fn polynomial_surface(n: usize, nvbl: usize, degree: i32) -> Anpass {
+    let mut state = 3;
+    let disps = Dmat::from_fn(n, nvbl, |_, _| 0.04 * lcg(&mut state));
+    let energies = Dvec::from_fn(n, |_, _| lcg(&mut state));
+    let mut cols = Vec::new();
+    let mut exps = vec![0; nvbl];
+    loop {
+        if exps.iter().sum::<i32>() <= degree {
+            cols.push(exps.clone());
+        }
+        let mut i = 0;
+        while i < nvbl && exps[i] == degree {
+            exps[i] = 0;
+            i += 1;
+        }
+        if i == nvbl {
+            break;
+        }
+        exps[i] += 1;
+    }
+    fixture(
+        disps,
+        energies,
+        na::DMatrix::from_fn(nvbl, cols.len(), |i, j| cols[j][i]),
+    )
+}
+
+/// a synthetic surface with `n` random points in 6 variables and every
+/// monomial through total degree 4
+#[cfg(feature = "rayon")]
+fn large_surface(n: usize) -> Anpass {
+    polynomial_surface(n, 6, 4)
+}
+
+#[test]
+fn test_build_design_matrix_powers() {
+    let anpass = polynomial_surface(200, 3, 10);
+    assert_eq!(
+        anpass.build_design_matrix_serial(),
+        anpass.build_design_matrix_naive()
+    );
+    let anpass = Anpass::load_file("testfiles/c3h2.in");
+    assert_eq!(
+        anpass.build_design_matrix_serial(),
+        anpass.build_design_matrix_naive()
+    );
+
+    // negative exponents and zero displacements take the same paths as the
+    // naive loop
+    let mut anpass = polynomial_surface(20, 2, 3);
+    anpass.disps[(0, 0)] = 0.0;
+    anpass.disps[(1, 1)] = 0.0;
+    anpass.exponents[(0, 1)] = -2;
+    anpass.exponents[(1, 2)] = -1;
+    let got = anpass.build_design_matrix_serial();
+    let want = anpass.build_design_matrix_naive();
+    assert_eq!(got.shape(), want.shape());
+    for (g, w) in got.iter().zip(want.iter()) {
+        assert!(g == w || (g.is_nan() && w.is_nan()), "{g} != {w}");
+    }
+    #[cfg(feature = "rayon")]
+    {
+        let got = anpass.build_design_matrix_par();
+        for (g, w) in got.iter().zip(want.iter()) {
+            assert!(g == w || (g.is_nan() && w.is_nan()), "{g} != {w}");
+        }
+    }
+}
+
+#[test]
+#[ignore]
+fn bench_build_design_matrix_powers() {
+    const N: usize = 5;
+    let anpass = polynomial_surface(20_000, 4, 10);
+
+    let now = std::time::Instant::now();
+    for _ in 0..N {
+        std::hint::black_box(anpass.build_design_matrix_naive());
+    }
+    let naive = now.elapsed();
+
+    let now = std::time::Instant::now();
+    for _ in 0..N {
+        std::hint::black_box(anpass.build_design_matrix_serial());
+    }
+    let memoized = now.elapsed();
+    println!(
+        "naive: {:.3} ms/iter, memoized: {:.3} ms/iter",
+        naive.as_secs_f64() * 1e3 / N as f64,
+        memoized.as_secs_f64() * 1e3 / N as f64,
+    );
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn test_build_design_matrix_par() {
+    let anpass = large_surface(500);
+    assert_eq!(
+        anpass.build_design_matrix_par(),
+        anpass.build_design_matrix_serial()
+    );
+    let anpass = Anpass::load_file("testfiles/c3h2.in");
+    assert_eq!(
+        anpass.build_design_matrix_par(),
+        anpass.build_design_matrix_serial()
+    );
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+#[ignore]
+fn bench_build_design_matrix() {
+    const N: usize = 5;
+    let anpass = large_surface(200_000);
+
+    let now = std::time::Instant::now();
+    for _ in 0..N {
+        std::hint::black_box(anpass.build_design_matrix_serial());
+    }
+    let serial = now.elapsed();
+
+    let now = std::time::Instant::now();
+    for _ in 0..N {
+        std::hint::black_box(anpass.build_design_matrix_par());
+    }
+    let parallel = now.elapsed();
+    println!(
+        "serial: {:.3} ms/iter, parallel: {:.3} ms/iter",
+        serial.as_secs_f64() * 1e3 / N as f64,
+        parallel.as_secs_f64() * 1e3 / N as f64,
+    );
+}
+
+#[test]
+fn test_fit_full() {
+    let anpass = Anpass::load_file("testfiles/c3h2.in");
+    let FitResult {
+        coeffs,
+        residuals,
+        rmsd,
+        x,
+    } = anpass.fit_full();
+    let (want, want_x) = anpass.fit();
+    assert_eq!(coeffs, want);
+    assert_eq!(x, want_x);
+    assert_eq!(residuals, anpass.point_residuals(&coeffs));
+    assert_eq!(rmsd, anpass.fit_statistics(&coeffs).rmsd);
+}
+
+#[test]
+fn test_try_newton() {
+    let anpass = Anpass::load_file("testfiles/c3h2.in");
+    let (coeffs, _) = anpass.fit();
+    assert_eq!(
+        anpass.try_newton(&coeffs).unwrap(),
+        anpass.newton(&coeffs).unwrap()
+    );
+
+    let cubic =
+        |exps: &[i32]| poly(na::DMatrix::from_row_slice(1, exps.len(), exps));
+
+    // f = x + x³ has a zero Hessian at the origin
+    let got = cubic(&[1, 3]).try_newton(&na::dvector![1.0, 1.0]);
+    assert_eq!(
+        got,
+        Err(NewtonError::SingularHessian {
+            x: na::dvector![0.0]
+        })
+    );
+
+    // f = x + x² + x³ has no stationary point
+    let anpass = cubic(&[1, 2, 3]);
+    let coeffs = na::dvector![1.0, 1.0, 1.0];
+    let Err(NewtonError::NotConverged { x, grad_norm }) =
+        anpass.try_newton(&coeffs)
+    else {
+        panic!("expected NotConverged");
+    };
+    assert_eq!(grad_norm, anpass.grad(&x, &coeffs).norm());
+    assert!(grad_norm > 0.5);
+    let err = anpass.newton(&coeffs).unwrap_err();
+    assert!(matches!(err, AnpassError::Newton(_)));
+    assert!(err.to_string().starts_with("too many Newton iterations"));
+}
+
+#[test]
+fn test_newton_with() {
+    let anpass = Anpass::load_file("testfiles/c3h2.in");
+    let (coeffs, _) = anpass.fit();
+    let want = anpass.newton(&coeffs).unwrap();
+    assert_eq!(
+        anpass
+            .newton_with(&coeffs, NewtonOptions::default())
+            .unwrap(),
+        want
+    );
+
+    // full steps converge to the same point
+    let opts = NewtonOptions {
+        step_scale: 1.0,
+        tol: 1e-12,
+        ..Default::default()
+    };
+    let (x, kind) = anpass.newton_with(&coeffs, opts).unwrap();
+    assert_eq!(kind, want.1);
+    // the default half steps stop a few times tol short of convergence
+    assert_abs_diff_eq!(x, want.0, epsilon = 5e-8);
+
+    // but too few iterations do not
+    let opts = NewtonOptions {
+        max_iter: 2,
+        ..Default::default()
+    };
+    assert!(matches!(
+        anpass.newton_with(&coeffs, opts),
+        Err(NewtonError::NotConverged { .. })
+    ));
+}
+
+#[test]
+fn test_newton_line_search() {
+    // f = -x - 2x² - x³ + 2x⁴, on which the fixed half steps get stuck
+    // bouncing around x = 1
+    let anpass = poly(na::DMatrix::from_row_slice(1, 4, &[1, 2, 3, 4]));
+    let coeffs = na::dvector![-1.0, -2.0, -1.0, 2.0];
+    assert!(matches!(
+        anpass.try_newton(&coeffs),
+        Err(NewtonError::NotConverged { .. })
+    ));
+    let opts = NewtonOptions {
+        line_search: true,
+        ..Default::default()
+    };
+    let (x, kind) = anpass.newton_with(&coeffs, opts).unwrap();
+    assert_eq!(kind, StatKind::Min);
+    assert!(anpass.grad(&x, &coeffs).norm() < 1e-6);
+
+    // on a well-behaved surface, the line search accepts every step
+    let anpass = Anpass::load_file("testfiles/c3h2.in");
+    let (coeffs, _) = anpass.fit();
+    assert_eq!(
+        anpass.newton_with(&coeffs, opts).unwrap(),
+        anpass.newton(&coeffs).unwrap()
+    );
+}
+
+#[test]
+fn test_newton_from() {
+    let anpass = Anpass::load_file("testfiles/c3h2.in");
+    let (coeffs, _) = anpass.fit();
+    let origin = Dvec::zeros(anpass.n_variables());
+    let (want, kind) = anpass.newton(&coeffs).unwrap();
+    let (got, got_kind) = anpass.newton_from(&coeffs, &origin).unwrap();
+    assert_eq!(got, want);
+    assert_eq!(got_kind, kind);
+
+    // starting from a nearby point converges in fewer iterations
+    let opts = NewtonOptions {
+        max_iter: 10,
+        ..Default::default()
+    };
+    assert!(anpass.newton_with(&coeffs, opts).is_err());
+    let x0 = want.map(|x| x * (1.0 + 1e-6));
+    let (got, got_kind) = anpass.newton_from(&coeffs, &x0).unwrap();
+    assert_eq!(got_kind, kind);
+    assert_abs_diff_eq!(got, want, epsilon = 5e-8);
+}
+
+#[test]
+fn test_newton_levenberg_marquardt() {
+    // f = x/2 - x² + x⁴, with minima at x = -0.809 and 0.5 and a maximum at
+    // x = 0.309. the Hessian is negative at the origin, so the plain Newton
+    // step heads uphill to the maximum
+    let anpass = poly(na::DMatrix::from_row_slice(1, 3, &[1, 2, 4]));
+    let coeffs = na::dvector![0.5, -1.0, 1.0];
+    let (x, kind) = anpass.newton(&coeffs).unwrap();
+    assert_eq!(kind, StatKind::Max);
+    assert_abs_diff_eq!(x[0], (5f64.sqrt() - 1.0) / 4.0, epsilon = 1e-7);
+
+    let opts = NewtonOptions {
+        method: StepMethod::LevenbergMarquardt,
+        ..Default::default()
+    };
+    let (x, kind) = anpass.newton_with(&coeffs, opts).unwrap();
+    assert_eq!(kind, StatKind::Min);
+    assert_abs_diff_eq!(x[0], -(5f64.sqrt() + 1.0) / 4.0, epsilon = 1e-7);
+
+    // where the Hessian is positive definite, the shift is never applied
+    let anpass = Anpass::load_file("testfiles/c3h2.in");
+    let (coeffs, _) = anpass.fit();
+    assert_eq!(
+        anpass.newton_with(&coeffs, opts).unwrap(),
+        anpass.newton(&coeffs).unwrap()
+    );
+}
+
+#[test]
+fn test_newton_eigen() {
+    // f = x + y + x² - y² + x⁴ has a single stationary point, a saddle at
+    // y = 1/2 and the real root of 1 + 2x + 4x³
+    let anpass = poly(na::DMatrix::from_row_slice(
+        2,
+        5,
+        &[1, 0, 2, 0, 4, 0, 1, 0, 2, 0],
+    ));
+    let coeffs = na::dvector![1.0, 1.0, 1.0, -1.0, 1.0];
+    let opts = NewtonOptions {
+        method: StepMethod::Eigen,
+        ..Default::default()
+    };
+    let (x, kind) = anpass.newton_with(&coeffs, opts).unwrap();
+    assert_eq!(kind, StatKind::Stat);
+    assert_abs_diff_eq!(
+        1.0 + 2.0 * x[0] + 4.0 * x[0].powi(3),
+        0.0,
+        epsilon = 1e-7
+    );
+    assert_abs_diff_eq!(x[1], 0.5, epsilon = 1e-7);
+
+    // negating the surface turns every minimum into a maximum
+    let anpass = Anpass::load_file("testfiles/c3h2.in");
+    let (coeffs, _) = anpass.fit();
+    let (want, _) = anpass.newton(&coeffs).unwrap();
+    let (x, kind) = anpass.newton_with(&-coeffs, opts).unwrap();
+    assert_eq!(kind, StatKind::Max);
+    assert_abs_diff_eq!(x, want, epsilon = 1e-8);
+}
+
+#[test]
+fn test_newton_with_history() {
+    let anpass = Anpass::load_file("testfiles/c3h2.in");
+    let (coeffs, _) = anpass.fit();
+    let opts = NewtonOptions::default();
+    let (got, history) = anpass.newton_with_history(&coeffs, opts);
+    assert_eq!(got, anpass.try_newton(&coeffs));
+    let (x, _) = got.unwrap();
+    assert!(!history.is_empty());
+    for (i, step) in history.iter().enumerate() {
+        assert_eq!(step.iteration, i);
+    }
+    let NewtonStep { x: first, .. } = &history[0];
+    assert_eq!(first, &Dvec::zeros(anpass.n_variables()));
+    let last = history.last().unwrap();
+    assert_eq!(last.x, x);
+    assert!(last.step_norm < history[0].step_norm);
+    assert!(last.grad_norm < history[0].grad_norm);
+
+    // a failed run records every iteration up to the limit
+    let opts = NewtonOptions {
+        max_iter: 3,
+        ..Default::default()
+    };
+    let (got, history) = anpass.newton_with_history(&coeffs, opts);
+    let Err(NewtonError::NotConverged { grad_norm, .. }) = got else {
+        panic!("expected NotConverged, got {got:?}");
+    };
+    assert_eq!(history.len(), 3);
+    assert!(grad_norm < history[2].grad_norm);
+}
+
+#[test]
+fn test_newton_negative_step() {
+    // f = -x + x² - y + y², with its minimum at (1/2, 1/2). every component
+    // of every step is negative, so a convergence test on the signed
+    // components, rather than their magnitudes, would accept the origin
+    let anpass =
+        poly(na::DMatrix::from_row_slice(2, 4, &[1, 2, 0, 0, 0, 0, 1, 2]));
+    let coeffs = na::dvector![-1.0, 1.0, -1.0, 1.0];
+    let (got, history) =
+        anpass.newton_with_history(&coeffs, NewtonOptions::default());
+    let (x, kind) = got.unwrap();
+    assert_eq!(kind, StatKind::Min);
+    assert_abs_diff_eq!(x, na::dvector![0.5, 0.5], epsilon = 1e-7);
+    assert!(history.len() > 1);
+    assert!(history[1].x.iter().all(|&x| x > 0.0));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde_traits() {
+    fn is_serde<T: serde::Serialize + serde::de::DeserializeOwned>() {}
+    is_serde::<Anpass>();
+    is_serde::<Bias>();
+    is_serde::<StatKind>();
+    is_serde::<Fc>();
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn test_json() {
+    let mut anpass = Anpass::load_file("testfiles/c3h2.in");
+    let got = Anpass::from_json(&anpass.to_json()).unwrap();
+    assert_eq!(got, anpass);
+    assert_eq!(got.header(), anpass.header());
+
+    anpass.bias = Some(Bias {
+        disp: Dvec::repeat(anpass.n_variables(), 0.5),
+        energy: -1.25,
+    });
+    anpass.weights = Some(Dvec::repeat(anpass.disps.nrows(), 2.0));
+    let got = Anpass::from_json(&anpass.to_json()).unwrap();
+    assert_eq!(got, anpass);
+
+    let json = r#"{"disps": [[0.1, 0.2], [0.3, 0.4]], "energies": [1.0, 2.0],
+                   "exponents": [[1, 0], [0, 1]]}"#;
+    let got = Anpass::from_json(json).unwrap();
+    assert_eq!(got.disps, na::dmatrix![0.1, 0.2; 0.3, 0.4]);
+    assert_eq!(got.exponents, na::dmatrix![1, 0; 0, 1]);
+    assert_eq!(got.bias, None);
+
+    let ragged = r#"{"disps": [[0.1, 0.2], [0.3]], "energies": [1.0, 2.0],
+                     "exponents": [[1, 0], [0, 1]]}"#;
+    assert!(Anpass::from_json(ragged).is_err());
+    assert!(Anpass::from_json("{").is_err());
+
+    // dimensions are checked as by AnpassBuilder::build
+    let err = |json: &str| Anpass::from_json(json).unwrap_err().to_string();
+    assert_eq!(
+        err(r#"{"disps": [[0.1, 0.2], [0.3, 0.4]], "energies": [1.0],
+                "exponents": [[1, 0], [0, 1]]}"#),
+        "1 energies given for 2 displacements"
+    );
+    assert_eq!(
+        err(
+            r#"{"disps": [[0.1, 0.2], [0.3, 0.4]], "energies": [1.0, 2.0],
+                "exponents": [[1, 0]]}"#
+        ),
+        "exponents have 1 rows, expected 2 to match the columns of disps"
+    );
+    assert_eq!(
+        err(
+            r#"{"disps": [[0.1, 0.2], [0.3, 0.4]], "energies": [1.0, 2.0],
+                "exponents": [[1, 0], [0, 1]], "weights": [1.0]}"#
+        ),
+        "1 weights given for 2 displacements"
+    );
+    assert_eq!(
+        err(
+            r#"{"disps": [[0.1, 0.2], [0.3, 0.4]], "energies": [1.0, 2.0],
+                "exponents": [[1, 0], [0, 1]],
+                "bias": {"disp": [0.0], "energy": 0.0}}"#
+        ),
+        "bias has 1 coordinates, expected 2"
+    );
+    assert_eq!(
+        err(
+            r#"{"disps": [[0.1, 0.2], [0.3, 0.4]], "energies": [1.0, 2.0],
+                "exponents": [[1, 0], [0, 1]], "surfaces": [[1.0, 3.0]]}"#
+        ),
+        "surfaces have 1 rows for 2 displacements"
+    );
+
+    // and multiple surfaces survive the round trip
+    let anpass = Anpass::load(h2o_multi().as_bytes());
+    assert_eq!(Anpass::from_json(&anpass.to_json()).unwrap(), anpass);
+}